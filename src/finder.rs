@@ -0,0 +1,417 @@
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc::Sender,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::style::ObjectStyle;
+
+use crate::{
+    Canvas,
+    ColorMap,
+    Drawable,
+    Event,
+    Rect,
+    StrUtils,
+    display_name,
+};
+
+// how often the walker hands its findings over to the ui
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+struct Match {
+    idx: usize,          // index into the candidates
+    score: i64,
+    positions: Vec<usize>, // char indices of the matched chars
+}
+
+pub struct Finder {
+    pub geo: Rect,
+    id: usize,
+    root: PathBuf,
+
+    candidates: Vec<PathBuf>, // relative to root
+    matches: Vec<Match>,      // matching candidates, best first
+    pattern: String,
+
+    sel: usize,
+    scroll: usize,
+
+    done: bool,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Finder {
+    // Starts walking the subtree of `root` in the background. The findings
+    // are reported as `Event::Found` tagged with `id`
+    pub fn new(geo: Rect, id: usize, root: PathBuf, events: Sender<Event>) -> Self {
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        {
+            let root = root.clone();
+            let cancel = cancel.clone();
+
+            thread::spawn(move || {
+                let mut batch = vec![];
+                let mut last_send = Instant::now();
+
+                walk(&root, Path::new(""), &cancel, &mut |rel| {
+                    batch.push(rel);
+
+                    if last_send.elapsed() >= BATCH_INTERVAL {
+                        last_send = Instant::now();
+                        let batch = std::mem::take(&mut batch);
+                        return events.send(Event::Found(id, batch)).is_ok();
+                    }
+                    true
+                });
+
+                if !cancel.load(Ordering::Relaxed) {
+                    let _ = events.send(Event::Found(id, batch));
+                    let _ = events.send(Event::FindDone(id));
+                }
+            });
+        }
+
+        Self {
+            geo: geo,
+            id: id,
+            root: root,
+
+            candidates: vec![],
+            matches: vec![],
+            pattern: String::new(),
+
+            sel: 0,
+            scroll: 0,
+
+            done: false,
+            cancel: cancel,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn add_candidates(&mut self, new: Vec<PathBuf>) {
+        let start = self.candidates.len();
+        self.candidates.extend(new);
+
+        for idx in start..self.candidates.len() {
+            if let Some(m) = self.score(idx) {
+                self.matches.push(m);
+            }
+        }
+        self.sort_matches();
+    }
+
+    pub fn set_done(&mut self) {
+        self.done = true;
+    }
+
+    pub fn set_pattern(&mut self, pattern: &str) {
+        self.pattern = pattern.to_owned();
+        self.matches = (0..self.candidates.len())
+            .filter_map(|idx| self.score(idx))
+            .collect();
+        self.sort_matches();
+
+        self.sel = 0;
+        self.scroll = 0;
+    }
+
+    pub fn inc_sel(&mut self, ofs: isize) {
+        if self.matches.is_empty() {
+            return;
+        }
+
+        let max_sel = self.matches.len() as isize - 1;
+        self.sel = (self.sel as isize + ofs).max(0).min(max_sel) as usize;
+
+        let h = self.geo.h as usize;
+        if self.sel >= self.scroll + h {
+            self.scroll = self.sel + 1 - h;
+        } else if self.sel < self.scroll {
+            self.scroll = self.sel;
+        }
+    }
+
+    // absolute path of the selected match
+    pub fn sel_path(&self) -> Option<PathBuf> {
+        self.matches.get(self.sel)
+            .map(|m| self.root.join(&self.candidates[m.idx]))
+    }
+
+    pub fn status(&self) -> String {
+        format!(
+            "[{}/{}{}]",
+            self.matches.len(),
+            self.candidates.len(),
+            if self.done { "" } else { "…" }
+        )
+    }
+
+    fn score(&self, idx: usize) -> Option<Match> {
+        let text = display_name(self.candidates[idx].as_os_str());
+
+        fuzzy_match(&self.pattern, &text).map(|(score, positions)| Match {
+            idx: idx,
+            score: score,
+            positions: positions,
+        })
+    }
+
+    fn sort_matches(&mut self) {
+        let candidates = &self.candidates;
+
+        // Best score first, then shortest path
+        self.matches.sort_by(|a, b| {
+            b.score.cmp(&a.score)
+             .then_with(|| {
+                 let alen = candidates[a.idx].as_os_str().len();
+                 let blen = candidates[b.idx].as_os_str().len();
+                 alen.cmp(&blen)
+             })
+        });
+    }
+}
+
+impl Drop for Finder {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<'a> Drawable<&ColorMap<'a>> for Finder {
+    fn get_geo(&self) -> Rect {
+        self.geo.clone()
+    }
+
+    fn draw(&mut self, d: &mut impl Canvas, c: &ColorMap) {
+        let w = self.geo.w as usize;
+        let rows = self.matches.iter().enumerate().skip(self.scroll);
+
+        for (y, (i, m)) in (0..self.geo.h).zip(rows) {
+            d.goto(0, y);
+
+            let mut text = display_name(self.candidates[m.idx].as_os_str());
+            text.ellipsize(w);
+            let len = text.chars().count();
+
+            let s = match i == self.sel {
+                true  => &c["Selected"],
+                false => &c["File"],
+            };
+
+            // Print runs of matched and unmatched chars
+            let mut run = String::new();
+            let mut run_matched = false;
+
+            for (ci, ch) in text.chars().enumerate() {
+                let matched = m.positions.contains(&ci);
+
+                if matched != run_matched && !run.is_empty() {
+                    print_run(d, c, s, &run, run_matched);
+                    run.clear();
+                }
+                run.push(ch);
+                run_matched = matched;
+            }
+            run.push_str(&format!("{0: <1$}", "", w - len));
+            print_run(d, c, s, &run, run_matched);
+        }
+    }
+}
+
+fn print_run(d: &mut impl Canvas, c: &ColorMap, s: &ObjectStyle, run: &str, matched: bool) {
+    match matched {
+        true  => d.print(s.apply_to(c["SearchMatch"].apply_to(run))),
+        false => d.print(s.apply_to(run)),
+    }
+}
+
+// Recursively calls `found` with the path of every entry under `dir`
+// relative to `root`. Symlinked directories are not followed.
+// Stops early when `found` returns false or the walk is cancelled
+fn walk(
+    root: &Path,
+    rel: &Path,
+    cancel: &AtomicBool,
+    found: &mut dyn FnMut(PathBuf) -> bool,
+) -> bool {
+    let entries = match root.join(rel).read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return true,
+    };
+
+    for e in entries {
+        if cancel.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let e = match e {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+
+        let path = rel.join(e.file_name());
+        let is_dir = e.file_type().map(|t| t.is_dir()).unwrap_or(false);
+
+        if !found(path.clone()) {
+            return false;
+        }
+
+        if is_dir && !walk(root, &path, cancel, found) {
+            return false;
+        }
+    }
+
+    true
+}
+
+// Scores how well `text` fuzzy matches `pattern`, the higher the better.
+// Every char of the pattern has to appear in order in the text. Matching is
+// smart case like the incremental search.
+// Returns the score and the char indices of the matched chars
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    if pattern.is_empty() {
+        return Some((0, vec![]));
+    }
+
+    let case_sensitive = pattern.chars().any(char::is_uppercase);
+    let fold = |c: char| match case_sensitive {
+        true  => c,
+        false => c.to_lowercase().next().unwrap_or(c),
+    };
+
+    let pat: Vec<char> = pattern.chars().map(fold).collect();
+    let txt: Vec<char> = text.chars().collect();
+
+    // Find the first occurence of the pattern as a subsequence...
+    let mut pi = 0;
+    let mut end = 0;
+    for (ti, &tc) in txt.iter().enumerate() {
+        if fold(tc) == pat[pi] {
+            pi += 1;
+            if pi == pat.len() {
+                end = ti;
+                break;
+            }
+        }
+    }
+    if pi < pat.len() {
+        return None;
+    }
+
+    // ...then walk back from its end to find the tightest window
+    let mut start = end;
+    let mut pi = pat.len();
+    for ti in (0..=end).rev() {
+        if fold(txt[ti]) == pat[pi - 1] {
+            pi -= 1;
+            if pi == 0 {
+                start = ti;
+                break;
+            }
+        }
+    }
+
+    // Basename matches are worth more than matches in the parent dirs
+    let base_start = txt.iter().rposition(|&c| c == '/').map_or(0, |i| i + 1);
+
+    let mut score = 0;
+    let mut positions = Vec::with_capacity(pat.len());
+    let mut pi = 0;
+    let mut prev: Option<usize> = None;
+
+    for ti in start..=end {
+        if pi == pat.len() {
+            break;
+        }
+        if fold(txt[ti]) != pat[pi] {
+            continue;
+        }
+
+        score += 16;
+
+        let boundary = match ti {
+            0 => true,
+            _ => {
+                let pc = txt[ti - 1];
+                "/_-. ".contains(pc) || (pc.is_lowercase() && txt[ti].is_uppercase())
+            },
+        };
+        if boundary {
+            score += 10;
+        }
+
+        match prev {
+            Some(p) if p + 1 == ti => score += 12,
+            Some(p) => score -= 3 + (ti - p - 1).min(10) as i64,
+            None => (),
+        }
+
+        if ti >= base_start {
+            score += 4;
+        }
+
+        positions.push(ti);
+        prev = Some(ti);
+        pi += 1;
+    }
+
+    // Prefer shorter candidates on otherwise equal matches
+    score -= (txt.len() / 16) as i64;
+
+    Some((score, positions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(pattern: &str, text: &str) -> i64 {
+        fuzzy_match(pattern, text).unwrap().0
+    }
+
+    #[test]
+    fn chars_in_order() {
+        assert_eq!(fuzzy_match("", "abc"), Some((0, vec![])));
+        assert_eq!(fuzzy_match("ac", "abc").map(|m| m.1), Some(vec![0, 2]));
+        assert_eq!(fuzzy_match("ca", "abc"), None);
+        assert_eq!(fuzzy_match("abcd", "abc"), None);
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(fuzzy_match("read", "README.md").is_some());
+        assert!(fuzzy_match("Read", "README.md").is_none());
+        assert!(fuzzy_match("READ", "README.md").is_some());
+    }
+
+    #[test]
+    fn tightest_window_is_highlighted() {
+        // The first a isn't part of the match, the window ends at the c
+        assert_eq!(fuzzy_match("abc", "a_xabc").map(|m| m.1), Some(vec![3, 4, 5]));
+    }
+
+    #[test]
+    fn better_matches_score_higher() {
+        // Consecutive chars
+        assert!(score("main", "src/main.rs") > score("main", "src/m_a_i_n.rs"));
+        // Word boundaries
+        assert!(score("fb", "foo_bar") > score("fb", "foobar"));
+        assert!(score("fb", "fooBar") > score("fb", "foobar"));
+        // The basename
+        assert!(score("lib", "x/lib") > score("lib", "lib/x"));
+        // Shorter candidates
+        assert!(score("a", "a") > score("a", "a_very_long_name_indeed"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::ffi::{
+    OsStr,
+    OsString,
+};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::{
+    Command,
+    Stdio,
+};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use crate::Event;
+
+// Ordered by how much it matters when aggregated into a dir
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileStatus {
+    Ignored,
+    Untracked,
+    Staged,
+    Modified,
+    Conflicted,
+}
+
+impl FileStatus {
+    pub fn marker(self) -> char {
+        match self {
+            FileStatus::Ignored    => '!',
+            FileStatus::Untracked  => '?',
+            FileStatus::Staged     => '+',
+            FileStatus::Modified   => '*',
+            FileStatus::Conflicted => 'U',
+        }
+    }
+
+    pub fn style_name(self) -> &'static str {
+        match self {
+            FileStatus::Ignored    => "GitIgnored",
+            FileStatus::Untracked  => "GitUntracked",
+            FileStatus::Staged     => "GitStaged",
+            FileStatus::Modified   => "GitModified",
+            FileStatus::Conflicted => "GitConflicted",
+        }
+    }
+
+    // "XY" of `git status --porcelain`
+    fn from_xy(x: u8, y: u8) -> Option<Self> {
+        match (x, y) {
+            (b'?', b'?') => Some(FileStatus::Untracked),
+            (b'!', b'!') => Some(FileStatus::Ignored),
+            (b'U', _) | (_, b'U') |
+            (b'A', b'A') | (b'D', b'D') => Some(FileStatus::Conflicted),
+            (_, b' ') if x != b' ' => Some(FileStatus::Staged),
+            (_, b' ') => None,
+            _ => Some(FileStatus::Modified),
+        }
+    }
+}
+
+// The status of a repository's work tree
+pub struct RepoStatus {
+    root: PathBuf,
+    files: HashMap<PathBuf, FileStatus>,
+    // whole untracked or ignored dirs, git doesn't list their contents
+    dirs: HashMap<PathBuf, FileStatus>,
+    // the worst status of the contents of every dir with changes
+    aggregated: HashMap<PathBuf, FileStatus>,
+}
+
+impl RepoStatus {
+    fn parse(root: &Path, output: &[u8]) -> Self {
+        let mut status = Self {
+            root: root.to_owned(),
+            files: HashMap::new(),
+            dirs: HashMap::new(),
+            aggregated: HashMap::new(),
+        };
+
+        // NUL separated "XY path" records, renames are followed by the
+        // original path
+        let mut records = output.split(|&b| b == 0);
+        while let Some(rec) = records.next() {
+            if rec.len() < 4 {
+                continue;
+            }
+
+            let (x, y) = (rec[0], rec[1]);
+            if x == b'R' || x == b'C' {
+                records.next();
+            }
+
+            let fs = match FileStatus::from_xy(x, y) {
+                Some(fs) => fs,
+                None => continue,
+            };
+
+            let rel = &rec[3..];
+            let is_dir = rel.ends_with(b"/");
+            let path = root.join(OsStr::from_bytes(rel));
+
+            // Ignored files don't make their parents interesting
+            if fs != FileStatus::Ignored {
+                for dir in path.ancestors().skip(1) {
+                    let agg = status.aggregated.entry(dir.to_owned()).or_insert(fs);
+                    *agg = (*agg).max(fs);
+
+                    if dir == root {
+                        break;
+                    }
+                }
+            }
+
+            match is_dir {
+                true  => status.dirs.insert(path, fs),
+                false => status.files.insert(path, fs),
+            };
+        }
+
+        status
+    }
+
+    pub fn get(&self, path: &Path) -> Option<FileStatus> {
+        if let Some(fs) = self.files.get(path).or_else(|| self.dirs.get(path)) {
+            return Some(*fs);
+        }
+        if let Some(fs) = self.aggregated.get(path) {
+            return Some(*fs);
+        }
+
+        // Everything in an untracked or ignored dir shares its status
+        path.ancestors()
+            .skip(1)
+            .take_while(|dir| *dir != self.root)
+            .find_map(|dir| self.dirs.get(dir).cloned())
+    }
+}
+
+// The root of the work tree `dir` is in
+pub fn repo_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|d| d.join(".git").exists())
+        .map(|d| d.to_owned())
+}
+
+// Names of the entries of `dir` that aren't excluded by `.gitignore`,
+// `.ignore` or the global git excludes. The ignore files of the parent
+// dirs apply too
+pub fn unignored_names(dir: &Path) -> HashSet<OsString> {
+    ignore::WalkBuilder::new(dir)
+        .max_depth(Some(1))
+        .hidden(false)
+        .build()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.depth() == 1)
+        .map(|e| e.file_name().to_owned())
+        .collect()
+}
+
+// Runs `git status` for the repository at `root` in the background and
+// reports the result as `Event::GitStatus`, without a status if git
+// fails
+pub fn fetch_status(root: PathBuf, events: Sender<Event>) {
+    thread::spawn(move || {
+        let output = Command::new("git")
+            .arg("-C").arg(&root)
+            .args(["status", "--porcelain", "-z", "--ignored"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output();
+
+        let status = match output {
+            Ok(ref output) if output.status.success() => {
+                Some(RepoStatus::parse(&root, &output.stdout))
+            },
+            _ => None,
+        };
+
+        let _ = events.send(Event::GitStatus(root, status));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xy_codes() {
+        assert!(FileStatus::from_xy(b'?', b'?') == Some(FileStatus::Untracked));
+        assert!(FileStatus::from_xy(b'!', b'!') == Some(FileStatus::Ignored));
+        assert!(FileStatus::from_xy(b'U', b'A') == Some(FileStatus::Conflicted));
+        assert!(FileStatus::from_xy(b'M', b' ') == Some(FileStatus::Staged));
+        assert!(FileStatus::from_xy(b'M', b'M') == Some(FileStatus::Modified));
+        assert!(FileStatus::from_xy(b' ', b' ').is_none());
+    }
+
+    #[test]
+    fn parse_porcelain() {
+        let root = Path::new("/repo");
+        let output = b" M src/a.rs\0R  new.rs\0old.rs\0?? junk/\0!! target/\0A  src/sub/b.rs\0";
+        let status = RepoStatus::parse(root, output);
+
+        let get = |p: &str| status.get(&root.join(p));
+        assert!(get("src/a.rs") == Some(FileStatus::Modified));
+        assert!(get("new.rs") == Some(FileStatus::Staged));
+        assert!(get("old.rs").is_none());
+
+        // Dirs get the worst status of what's in them
+        assert!(get("src") == Some(FileStatus::Modified));
+        assert!(get("src/sub") == Some(FileStatus::Staged));
+
+        // What's in untracked and ignored dirs shares their status, and
+        // ignored files don't mark their parents
+        assert!(get("junk/deep/file") == Some(FileStatus::Untracked));
+        assert!(get("target/debug") == Some(FileStatus::Ignored));
+        assert!(get("src/c.rs").is_none());
+    }
+}
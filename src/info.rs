@@ -0,0 +1,251 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs::Metadata;
+use std::os::unix::fs::{
+    FileTypeExt,
+    MetadataExt,
+    PermissionsExt,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Per row fields that can be shown next to the entry names. Not all of
+// them are in the default columns
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum InfoField {
+    Size,
+    MTime,
+    Perms,
+    Owner,
+    Group,
+    Links,
+}
+
+impl InfoField {
+    // Directories get no size, the size of the dir inode means nothing
+    pub fn format(self, meta: &Metadata) -> String {
+        match self {
+            InfoField::Size => match meta.is_dir() {
+                true  => String::new(),
+                false => human_size(meta.len()),
+            },
+            InfoField::MTime => format_time(meta.mtime()),
+            InfoField::Perms => perms_string(meta),
+            InfoField::Owner => user_name(meta.uid()),
+            InfoField::Group => group_name(meta.gid()),
+            InfoField::Links => meta.nlink().to_string(),
+        }
+    }
+}
+
+// 1023 -> "1023", 1536 -> "1.5K", 10 * 2^20 -> "10M"
+pub fn human_size(size: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+
+    if size < 1024 {
+        return size.to_string();
+    }
+
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match size < 10.0 {
+        true  => format!("{:.1}{}", size, UNITS[unit]),
+        false => format!("{:.0}{}", size, UNITS[unit]),
+    }
+}
+
+// Formats like ls does: the time of day for recent files, the year for
+// files older than half a year
+pub fn format_time(secs: i64) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+        "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    const HALF_YEAR: i64 = 60 * 60 * 24 * 365 / 2;
+
+    let tm = match local_tm(secs) {
+        Some(tm) => tm,
+        None => return String::from("?"),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let month = MONTHS[tm.tm_mon as usize % 12];
+
+    if (now - secs).abs() < HALF_YEAR {
+        format!("{} {:>2} {:02}:{:02}", month, tm.tm_mday, tm.tm_hour, tm.tm_min)
+    } else {
+        format!("{} {:>2}  {}", month, tm.tm_mday, tm.tm_year + 1900)
+    }
+}
+
+// "YYYY-MM-DD" in local time
+pub fn format_date(secs: i64) -> String {
+    let tm = match local_tm(secs) {
+        Some(tm) => tm,
+        None => return String::from("?"),
+    };
+
+    format!("{}-{:02}-{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday)
+}
+
+// `secs` since the epoch broken down in the local time zone, None if it's
+// out of range
+pub fn local_tm(secs: i64) -> Option<libc::tm> {
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        let t = secs as libc::time_t;
+
+        if libc::localtime_r(&t, &mut tm).is_null() {
+            return None;
+        }
+        Some(tm)
+    }
+}
+
+// "drwxr-xr-x" style permission string
+pub fn perms_string(meta: &Metadata) -> String {
+    let mode = meta.permissions().mode();
+
+    let ft = meta.file_type();
+    let kind = if ft.is_dir() {
+        'd'
+    } else if ft.is_symlink() {
+        'l'
+    } else if ft.is_fifo() {
+        'p'
+    } else if ft.is_socket() {
+        's'
+    } else if ft.is_char_device() {
+        'c'
+    } else if ft.is_block_device() {
+        'b'
+    } else {
+        '-'
+    };
+
+    let bit = |mask: u32, c: char| if mode & mask != 0 { c } else { '-' };
+    // the execute bit shares its spot with setuid, setgid and sticky
+    let exec = |x: u32, special: u32, set: char| match (mode & x != 0, mode & special != 0) {
+        (true, true)   => set,
+        (false, true)  => set.to_ascii_uppercase(),
+        (true, false)  => 'x',
+        (false, false) => '-',
+    };
+
+    let mut s = String::with_capacity(10);
+    s.push(kind);
+    s.push(bit(0o400, 'r'));
+    s.push(bit(0o200, 'w'));
+    s.push(exec(0o100, 0o4000, 's'));
+    s.push(bit(0o040, 'r'));
+    s.push(bit(0o020, 'w'));
+    s.push(exec(0o010, 0o2000, 's'));
+    s.push(bit(0o004, 'r'));
+    s.push(bit(0o002, 'w'));
+    s.push(exec(0o001, 0o1000, 't'));
+    s
+}
+
+thread_local! {
+    static USERS: RefCell<HashMap<u32, String>> = RefCell::new(HashMap::new());
+    static GROUPS: RefCell<HashMap<u32, String>> = RefCell::new(HashMap::new());
+}
+
+// Falls back to the numeric id for unknown users
+pub fn user_name(uid: u32) -> String {
+    USERS.with(|users| {
+        users.borrow_mut()
+             .entry(uid)
+             .or_insert_with(|| lookup_user(uid).unwrap_or_else(|| uid.to_string()))
+             .clone()
+    })
+}
+
+// Falls back to the numeric id for unknown groups
+pub fn group_name(gid: u32) -> String {
+    GROUPS.with(|groups| {
+        groups.borrow_mut()
+              .entry(gid)
+              .or_insert_with(|| lookup_group(gid).unwrap_or_else(|| gid.to_string()))
+              .clone()
+    })
+}
+
+fn lookup_user(uid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+
+    unsafe {
+        let mut pwd: libc::passwd = std::mem::zeroed();
+        let mut res: *mut libc::passwd = std::ptr::null_mut();
+
+        libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut res);
+
+        if res.is_null() {
+            return None;
+        }
+        Some(CStr::from_ptr(pwd.pw_name).to_string_lossy().into_owned())
+    }
+}
+
+fn lookup_group(gid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+
+    unsafe {
+        let mut grp: libc::group = std::mem::zeroed();
+        let mut res: *mut libc::group = std::ptr::null_mut();
+
+        libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut res);
+
+        if res.is_null() {
+            return None;
+        }
+        Some(CStr::from_ptr(grp.gr_name).to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, Permissions};
+    use crate::TestDir;
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(human_size(0), "0");
+        assert_eq!(human_size(1023), "1023");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(1536), "1.5K");
+        assert_eq!(human_size(10 * 1024 * 1024), "10M");
+        assert_eq!(human_size(1023 * 1024), "1023K");
+        assert_eq!(human_size(u64::MAX), "16E");
+    }
+
+    #[test]
+    fn perms_strings() {
+        let dir = TestDir::new("perms");
+        let file = dir.join("f");
+        fs::write(&file, "").unwrap();
+
+        let perms = |path: &std::path::Path, mode: u32| {
+            fs::set_permissions(path, Permissions::from_mode(mode)).unwrap();
+            perms_string(&fs::symlink_metadata(path).unwrap())
+        };
+
+        assert_eq!(perms(&file, 0o644), "-rw-r--r--");
+        assert_eq!(perms(&file, 0o4755), "-rwsr-xr-x");
+        assert_eq!(perms(&file, 0o2644), "-rw-r-Sr--");
+        assert_eq!(perms(&dir, 0o1777), "drwxrwxrwt");
+        assert_eq!(perms(&dir, 0o1700), "drwx-----T");
+    }
+}
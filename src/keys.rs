@@ -0,0 +1,163 @@
+// Keys without a char of their own are passed around as chars from
+// Unicode's private use area
+pub const UP: char        = '\u{e000}';
+pub const DOWN: char      = '\u{e001}';
+pub const RIGHT: char     = '\u{e002}';
+pub const LEFT: char      = '\u{e003}';
+pub const HOME: char      = '\u{e004}';
+pub const END: char       = '\u{e005}';
+pub const PAGE_UP: char   = '\u{e006}';
+pub const PAGE_DOWN: char = '\u{e007}';
+pub const DELETE: char    = '\u{e008}';
+
+const ESC: u8 = 0x1b;
+
+// Whether `c` is one of the keys above, which must not be typed as text
+pub fn is_special(c: char) -> bool {
+    ('\u{e000}'..='\u{e0ff}').contains(&c)
+}
+
+// Splits bytes read from the terminal into key presses. The escape
+// sequences of the keys above turn into their chars, other sequences and
+// alt combinations are dropped and a lone escape is the escape key.
+// Returns the keys and how many bytes they took, what's left is the start
+// of a char still being read
+pub fn parse(bytes: &[u8]) -> (Vec<char>, usize) {
+    let mut keys = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == ESC {
+            let (key, len) = parse_escape(&bytes[i..]);
+            keys.extend(key);
+            i += len;
+            continue;
+        }
+
+        let end = bytes[i..].iter().position(|&b| b == ESC).map_or(bytes.len(), |p| i + p);
+
+        match std::str::from_utf8(&bytes[i..end]) {
+            Ok(s) => {
+                keys.extend(s.chars());
+                i = end;
+            },
+            Err(e) => {
+                let valid = e.valid_up_to();
+                keys.extend(std::str::from_utf8(&bytes[i..i+valid]).unwrap().chars());
+                i += valid;
+
+                match e.error_len() {
+                    Some(garbage) => i += garbage,
+                    // The rest of the char comes with the next read
+                    None if end == bytes.len() => return (keys, i),
+                    // Cut short by an escape
+                    None => i = end,
+                }
+            },
+        }
+    }
+
+    (keys, i)
+}
+
+// The key of the escape sequence `bytes` starts with, if it's known, and
+// the length of the sequence
+fn parse_escape(bytes: &[u8]) -> (Option<char>, usize) {
+    match bytes.get(1) {
+        None | Some(&ESC) => (Some('\x1b'), 1),
+
+        // CSI: parameters, then a final byte from @ to ~
+        Some(b'[') => {
+            let end = match bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b)) {
+                Some(p) => p + 2,
+                None => return (None, bytes.len()),
+            };
+            // Modifiers after a ; are ignored
+            let params = bytes[2..end].split(|&b| b == b';').next().unwrap_or(&[]);
+
+            let key = match (bytes[end], params) {
+                (b'A', _) => Some(UP),
+                (b'B', _) => Some(DOWN),
+                (b'C', _) => Some(RIGHT),
+                (b'D', _) => Some(LEFT),
+                (b'H', _) => Some(HOME),
+                (b'F', _) => Some(END),
+                (b'~', b"1") | (b'~', b"7") => Some(HOME),
+                (b'~', b"4") | (b'~', b"8") => Some(END),
+                (b'~', b"3") => Some(DELETE),
+                (b'~', b"5") => Some(PAGE_UP),
+                (b'~', b"6") => Some(PAGE_DOWN),
+                _ => None,
+            };
+            (key, end + 1)
+        },
+
+        // SS3, sent for the arrows in application mode
+        Some(b'O') => {
+            let key = match bytes.get(2) {
+                Some(b'A') => Some(UP),
+                Some(b'B') => Some(DOWN),
+                Some(b'C') => Some(RIGHT),
+                Some(b'D') => Some(LEFT),
+                Some(b'H') => Some(HOME),
+                Some(b'F') => Some(END),
+                _ => None,
+            };
+            (key, bytes.len().min(3))
+        },
+
+        // Alt and a key, the whole char is skipped
+        Some(&b) => {
+            let len = match b {
+                0xf0..=0xff => 4,
+                0xe0..=0xef => 3,
+                0xc0..=0xdf => 2,
+                _ => 1,
+            };
+            (None, bytes.len().min(1 + len))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(parse(b"ab\r"), (vec!['a', 'b', '\r'], 3));
+        assert_eq!(parse("é€".as_bytes()), (vec!['é', '€'], 5));
+    }
+
+    #[test]
+    fn split_char_is_kept_for_later() {
+        let bytes = "a€".as_bytes();
+        assert_eq!(parse(&bytes[..2]), (vec!['a'], 1));
+    }
+
+    #[test]
+    fn lone_escape() {
+        assert_eq!(parse(b"\x1b"), (vec!['\x1b'], 1));
+        assert_eq!(parse(b"\x1b\x1b"), (vec!['\x1b', '\x1b'], 2));
+    }
+
+    #[test]
+    fn arrows_and_keys_after_them() {
+        assert_eq!(parse(b"\x1b[Ax\x1b[D"), (vec![UP, 'x', LEFT], 7));
+        assert_eq!(parse(b"\x1bOB"), (vec![DOWN], 3));
+        assert_eq!(parse(b"\x1b[1;5C"), (vec![RIGHT], 6));
+        assert_eq!(parse(b"\x1b[5~\x1b[3~"), (vec![PAGE_UP, DELETE], 8));
+    }
+
+    #[test]
+    fn unknown_sequences_and_alt_are_dropped() {
+        assert_eq!(parse(b"\x1b[200~a"), (vec!['a'], 7));
+        assert_eq!(parse(b"\x1bxy"), (vec!['y'], 3));
+        assert_eq!(parse("\x1béz".as_bytes()), (vec!['z'], 4));
+    }
+
+    #[test]
+    fn garbage_is_skipped() {
+        assert_eq!(parse(b"a\xffb"), (vec!['a', 'b'], 3));
+    }
+}
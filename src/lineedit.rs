@@ -0,0 +1,157 @@
+use crate::keys;
+
+// A line of text being edited with readline like keys
+#[derive(Clone)]
+pub struct LineEditor {
+    chars: Vec<char>,
+    cursor: usize, // in chars, up to the length for the end
+    killed: Vec<char>, // the last text cut, put back by ctrl-y
+}
+
+impl LineEditor {
+    pub fn new(text: &str, cursor: usize) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+
+        Self {
+            cursor: cursor.min(chars.len()),
+            chars: chars,
+            killed: vec![],
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // Applies an editing key. Returns whether the text changed, keys it
+    // doesn't know are ignored
+    pub fn input(&mut self, c: char) -> bool {
+        match c {
+            '\x01' | keys::HOME => self.cursor = 0,                // ctrl-a
+            '\x05' | keys::END  => self.cursor = self.chars.len(), // ctrl-e
+            '\x02' | keys::LEFT => self.cursor = self.cursor.saturating_sub(1), // ctrl-b
+            '\x06' | keys::RIGHT => self.cursor = (self.cursor + 1).min(self.chars.len()), // ctrl-f
+
+            // backspace and ctrl-h
+            '\x7f' | '\x08' if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+                return true;
+            },
+
+            // ctrl-d
+            '\x04' | keys::DELETE if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+                return true;
+            },
+
+            // ctrl-k
+            '\x0b' => return self.kill(self.cursor, self.chars.len()),
+
+            // ctrl-u
+            '\x15' => return self.kill(0, self.cursor),
+
+            // ctrl-w, back to the start of the word
+            '\x17' => {
+                let start = self.chars[..self.cursor].iter()
+                    .rposition(|c| !c.is_whitespace())
+                    .map_or(0, |end| {
+                        self.chars[..end].iter()
+                            .rposition(|c| c.is_whitespace())
+                            .map_or(0, |space| space + 1)
+                    });
+                return self.kill(start, self.cursor);
+            },
+
+            // ctrl-y
+            '\x19' => {
+                let killed = self.killed.clone();
+                let n = killed.len();
+
+                self.chars.splice(self.cursor..self.cursor, killed);
+                self.cursor += n;
+                return n > 0;
+            },
+
+            c if !c.is_control() && !keys::is_special(c) => {
+                self.chars.insert(self.cursor, c);
+                self.cursor += 1;
+                return true;
+            },
+
+            _ => (),
+        }
+
+        false
+    }
+
+    // Cuts the chars from `start` to `end`, keeping them for ctrl-y
+    fn kill(&mut self, start: usize, end: usize) -> bool {
+        if start >= end {
+            return false;
+        }
+
+        self.killed = self.chars.drain(start..end).collect();
+        self.cursor = start;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Types `keys` into an editor for `text` with the cursor at `cursor`
+    fn edit(text: &str, cursor: usize, keys: &str) -> (String, usize) {
+        let mut editor = LineEditor::new(text, cursor);
+        for c in keys.chars() {
+            editor.input(c);
+        }
+        (editor.text(), editor.cursor())
+    }
+
+    #[test]
+    fn typing_and_moving() {
+        assert_eq!(edit("", 0, "ab"), (String::from("ab"), 2));
+        assert_eq!(edit("ac", 1, "b"), (String::from("abc"), 2));
+        assert_eq!(edit("abc", 10, "\x01x\x05y"), (String::from("xabcy"), 5));
+        assert_eq!(edit("ab", 0, "\x02\x02\x06\x06\x06z"), (String::from("abz"), 3));
+    }
+
+    #[test]
+    fn special_keys_move_but_are_never_typed() {
+        let keys: String = [keys::LEFT, keys::LEFT, keys::DELETE, keys::HOME, keys::UP].iter().collect();
+        assert_eq!(edit("abc", 3, &keys), (String::from("ac"), 0));
+    }
+
+    #[test]
+    fn deleting() {
+        assert_eq!(edit("abc", 3, "\x7f\x08"), (String::from("a"), 1));
+        assert_eq!(edit("abc", 0, "\x7f\x04"), (String::from("bc"), 0));
+        assert_eq!(edit("abc", 3, "\x04"), (String::from("abc"), 3));
+    }
+
+    #[test]
+    fn kill_and_yank() {
+        assert_eq!(edit("abc def", 3, "\x0b"), (String::from("abc"), 3));
+        assert_eq!(edit("abc def", 3, "\x15"), (String::from(" def"), 0));
+        assert_eq!(edit("abc def", 3, "\x15\x05\x19"), (String::from(" defabc"), 7));
+        assert_eq!(edit("one two  ", 9, "\x17"), (String::from("one "), 4));
+        assert_eq!(edit("one two", 7, "\x17\x17"), (String::from(""), 0));
+    }
+
+    #[test]
+    fn input_reports_changes() {
+        let mut editor = LineEditor::new("a", 1);
+        assert!(editor.input('b'));
+        assert!(!editor.input('\x01'));
+        assert!(!editor.input('\x7f'));
+        assert!(!editor.input('\x19'));
+        assert!(editor.input('\x0b'));
+        assert!(editor.input('\x19'));
+    }
+}
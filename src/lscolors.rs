@@ -0,0 +1,149 @@
+use crossterm::style::{
+    Attribute,
+    Color,
+    ObjectStyle,
+};
+
+// `LS_COLORS` keys and the style names they fill in
+const KINDS: [(&str, &str); 14] = [
+    ("fi", "File"),
+    ("di", "Directory"),
+    ("ln", "Symlink"),
+    ("or", "BrokenLink"),
+    ("pi", "Fifo"),
+    ("so", "Socket"),
+    ("bd", "BlockDevice"),
+    ("cd", "CharDevice"),
+    ("ex", "Executable"),
+    ("su", "Setuid"),
+    ("sg", "Setgid"),
+    ("st", "Sticky"),
+    ("ow", "OtherWritable"),
+    ("tw", "StickyOtherWritable"),
+];
+
+// Parses `LS_COLORS` ("di=01;34:*.tar=01;31:...") into styles named like
+// the theme's. Suffix patterns keep their leading `*` and are lowercased, as
+// ls matches them case insensitively.
+// Unknown keys and `ln=target` are skipped
+pub fn parse(ls_colors: &str) -> Vec<(String, ObjectStyle)> {
+    let mut styles = vec![];
+
+    for item in ls_colors.split(':') {
+        let (key, codes) = match item.find('=') {
+            Some(i) => (&item[..i], &item[i+1..]),
+            None => continue,
+        };
+
+        let name = if key.starts_with('*') {
+            key.to_lowercase()
+        } else {
+            match KINDS.iter().find(|(k, _)| *k == key) {
+                Some((_, name)) if codes != "target" => name.to_string(),
+                _ => continue,
+            }
+        };
+
+        if let Some(style) = parse_sgr(codes) {
+            styles.push((name, style));
+        }
+    }
+
+    styles
+}
+
+// "01;38;5;208" -> bold with color 208
+fn parse_sgr(codes: &str) -> Option<ObjectStyle> {
+    let mut style = ObjectStyle {
+        fg_color: None,
+        bg_color: None,
+        attrs: vec![],
+    };
+
+    let codes: Vec<u8> = codes.split(';')
+        .map(|c| match c {
+            "" => Some(0),
+            c => c.parse().ok(),
+        })
+        .collect::<Option<_>>()?;
+    let mut codes = codes.into_iter();
+
+    while let Some(code) = codes.next() {
+        match code {
+            0 => {
+                style.fg_color = None;
+                style.bg_color = None;
+                style.attrs.clear();
+            },
+            1 => style.attrs.push(Attribute::Bold),
+            2 => style.attrs.push(Attribute::Dim),
+            3 => style.attrs.push(Attribute::Italic),
+            4 => style.attrs.push(Attribute::Underlined),
+            5 => style.attrs.push(Attribute::SlowBlink),
+            6 => style.attrs.push(Attribute::RapidBlink),
+            7 => style.attrs.push(Attribute::Reverse),
+            8 => style.attrs.push(Attribute::Hidden),
+            9 => style.attrs.push(Attribute::CrossedOut),
+
+            30..=37 => style.fg_color = Some(Color::AnsiValue(code - 30)),
+            90..=97 => style.fg_color = Some(Color::AnsiValue(code - 90 + 8)),
+            39 => style.fg_color = None,
+            38 => style.fg_color = Some(extended_color(&mut codes)?),
+
+            40..=47 => style.bg_color = Some(Color::AnsiValue(code - 40)),
+            100..=107 => style.bg_color = Some(Color::AnsiValue(code - 100 + 8)),
+            49 => style.bg_color = None,
+            48 => style.bg_color = Some(extended_color(&mut codes)?),
+
+            _ => (),
+        }
+    }
+
+    Some(style)
+}
+
+// The rest of a 38/48 code: "5;n" or "2;r;g;b"
+fn extended_color(codes: &mut impl Iterator<Item = u8>) -> Option<Color> {
+    match codes.next()? {
+        5 => Some(Color::AnsiValue(codes.next()?)),
+        2 => Some(Color::Rgb {
+            r: codes.next()?,
+            g: codes.next()?,
+            b: codes.next()?,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Color can't be compared directly
+    fn fg(style: &ObjectStyle) -> String {
+        format!("{:?}", style.fg_color)
+    }
+
+    #[test]
+    fn kinds_and_suffixes() {
+        let styles = parse("di=01;34:ln=target:*.TAR=31:xx=32:ex=38;5;208");
+        let names: Vec<&str> = styles.iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(names, ["Directory", "*.tar", "Executable"]);
+        assert_eq!(styles[0].1.attrs, [Attribute::Bold]);
+        assert_eq!(fg(&styles[0].1), "Some(AnsiValue(4))");
+        assert_eq!(fg(&styles[1].1), "Some(AnsiValue(1))");
+        assert_eq!(fg(&styles[2].1), "Some(AnsiValue(208))");
+    }
+
+    #[test]
+    fn sgr_codes() {
+        let style = parse_sgr("1;4;0;97;48;2;1;2;3").unwrap();
+        assert!(style.attrs.is_empty());
+        assert_eq!(fg(&style), "Some(AnsiValue(15))");
+        assert_eq!(format!("{:?}", style.bg_color), "Some(Rgb { r: 1, g: 2, b: 3 })");
+
+        assert!(parse_sgr("38;5").is_none());
+        assert!(parse_sgr("1;x").is_none());
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::redundant_field_names, clippy::needless_return, clippy::len_zero)]
+
 // extern crate termion;
 // use termion::{ color,
 //     style,
@@ -19,6 +21,19 @@
 
 extern crate crossterm;
 
+mod finder;
+mod git;
+mod info;
+mod keys;
+mod lineedit;
+mod lscolors;
+mod ops;
+mod rename;
+mod size;
+mod state;
+mod trash;
+mod watch;
+
 use crossterm::{
     Screen,
     Crossterm,
@@ -30,19 +45,79 @@ use crossterm::{
     cursor,
 };
 
-// use std::io::Read;
+use std::io::Read;
 use std::io::Write;
 // use std::io;
-use std::borrow::Cow;
 use std::vec::Vec;
-use std::fs::File;
+use std::cell::OnceCell;
+use std::fs::{
+    self,
+    DirEntry,
+    File,
+    FileType,
+    Metadata,
+};
 use std::path::{
+    Component,
     Path,
     PathBuf,
 };
 use std::string::String;
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::rc::Rc;
+use std::ffi::{
+    OsStr,
+    OsString,
+};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{
+    FileTypeExt,
+    PermissionsExt,
+};
+use std::sync::mpsc::{
+    self,
+    Receiver,
+    Sender,
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use finder::Finder;
+use git::RepoStatus;
+use info::InfoField;
+use lineedit::LineEditor;
+use watch::Watcher;
+
+// == CONSTS ==
+
+// how many bytes of a file are loaded for its preview
+const FILE_PREVIEW_LEN: u64 = 4096;
+
+// info fields are dropped to leave the names at least this much room
+const MIN_NAME_WIDTH: usize = 8;
+
+// how often directory scans hand their entries over to the ui
+const SCAN_BATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+// width of the bars of the disk usage mode, without the brackets
+const USAGE_BAR_WIDTH: usize = 8;
+
+// rows kept between the selection and the column edges unless
+// --scroll-off=N says otherwise
+const DEFAULT_SCROLL_OFF: usize = 2;
+
+// how often the key reader checks whether it's been paused
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 // == TYPES ==
 
@@ -57,49 +132,242 @@ type Result = std::result::Result<(), String>;
 struct RootWin {
     term: Crossterm,
     screen: Screen,
+    // the terminal modes from before and while in raw mode, swapped when
+    // other programs take over the terminal
+    cooked: Option<libc::termios>,
+    raw: Option<libc::termios>,
 
     geo: Rect,
     draw_area: Option<Rect>,
 }
 
+#[derive(Clone, Copy)]
 enum SortOrder {
     Name,
+    Size, // largest first, dirs by their computed size
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FilterKind {
+    Substring,
+    Glob,
+    Regex,
+}
+
+#[derive(Clone)]
+struct Filter {
+    kind: FilterKind,
+    pattern: String,
+    re: Regex,
+}
+
+#[derive(Clone)]
+struct Search {
+    re: Regex,
+}
+
+// A directory entry with what the scan told us about it, so drawing and
+// sorting don't need to stat it again
+#[derive(Clone)]
+struct Entry {
+    path: PathBuf,
+    file_type: Option<FileType>, // of the entry itself, symlinks not followed
+    is_dir: bool,                // symlinks followed
+    is_file: bool,               // symlinks followed
+    link_target: Option<PathBuf>, // what a symlink points to
+    broken_link: bool,           // a symlink to nothing
+    dir_size: Option<u64>,       // total size of a dir's contents, if computed
+    partial_size: Option<u64>,   // what's been counted so far while it's computed
+    meta: OnceCell<Option<Metadata>>, // lstat, loaded on first use
 }
 
 #[derive(Clone)]
 struct DirView {
     geo: Rect,
     dir: PathBuf,
-    sel: Vec<usize>, // list of delected indices
+    sel: Vec<usize>, // the cursor as sel[0], an index into sorted_indices
+    marks: Vec<usize>, // marked entries by their index in `entries`
     scroll: usize,
-    entries: Vec<PathBuf>, // list of entries in the original order
-    sorted_indices: Vec<usize>, // indices of entries in sorted order
+    scroll_off: usize, // rows kept between the selection and the edges
+    entries: Vec<Entry>, // list of entries in the original order
+    sorted_indices: Vec<usize>, // indices of shown entries in sorted order
+    sort_order: SortOrder,
+    filter: Option<Filter>,
+    search: Option<Search>, // matches are highlighted when drawn
+    info: Vec<InfoField>,   // fields shown next to the names
+    git: Option<Rc<RepoStatus>>, // of the repository the dir is in
+    usage: bool,            // whether to show the disk usage bars
+    usage_total: Option<u64>, // size of all the entries for the bars, once needed
+    trash: Option<Rc<HashMap<OsString, trash::TrashInfo>>>, // if it's the trash
+    editing: Option<LineEditor>, // the cursor's new name while it's renamed
+    renames: Option<rename::Renames>, // new names waiting to be confirmed
+
+    scan: Option<Scan>,     // the scan in progress
+    pending_sel: Option<OsString>, // name to select once it's scanned
+    pending_scroll: Option<usize>, // scroll to restore along with it
+    error: Option<String>,  // why the dir couldn't be read
+}
+
+#[derive(Clone)]
+struct Scan {
+    id: usize,
+    cancel: Arc<AtomicBool>,
+    replace: bool,       // whether the entries replace the current ones
+    incoming: Vec<Entry>, // entries kept aside until a rescan is done
 }
 
 struct FileView {
     geo: Rect,
     path: PathBuf,
-    file: File,
+    buffer: Vec<u8>,
     scroll: usize,
+    error: Option<String>, // why the file couldn't be read
 }
 
+#[allow(clippy::large_enum_variant)]
 enum View {
     Dir(DirView),
     File(FileView),
 }
 
 // TODO: Find better names for these actions
+#[derive(Clone, Copy)]
 enum Action {
     Quit,
     MoveDown(usize),
     MoveUp(usize),
     MoveLeft(usize),
     MoveRight(usize),
+    PageDown,
+    PageUp,
+    HalfPageDown,
+    HalfPageUp,
+    GoFirst,
+    GoLast,
+    GoMiddle,
+    Filter,
+    ClearFilter,
+    Search,
+    SearchNext,
+    SearchPrev,
+    Find,
+    ToggleInfo,
+    CancelScan,
+    FollowLink,
+    ToggleIgnored,
+    ComputeSizes,
+    CycleSort,
+    DiskUsage,
+    Delete,
+    Trash,
+    Rename,
+    BulkRename,
+    BatchRename,
+    NewFile,
+    NewDir,
+    NewSymlink,
+    NewHardlink,
+    GotoTrash,
+    Restore,
+    ToggleMark,
+    Yank,
+    Cut,
+    Paste,
+}
+
+// Anything the main loop has to react to
+enum Event {
+    Key(char),
+    Found(usize, Vec<PathBuf>), // finder id and paths found
+    FindDone(usize),
+    DirChanged(PathBuf),
+    Scanned(PathBuf, usize, Vec<Entry>), // dir, scan id and entries found
+    ScanDone(PathBuf, usize, Option<String>), // and the error if it failed
+    GitStatus(PathBuf, Option<RepoStatus>),   // repository root and status
+    DirSize(PathBuf, u64, bool), // dir, total so far and whether it's done
+    UsageProgress(PathBuf, u64),              // root and bytes counted so far
+    UsageDone(PathBuf, HashMap<PathBuf, u64>), // root and the totals of its dirs
+    Deleted(PathBuf, Option<String>),         // and the error if it failed
+    Trashed(PathBuf, Option<String>),         // and the error if it failed
+    Restored(PathBuf, std::result::Result<PathBuf, String>), // to where, or why not
+    PasteProgress(ops::Progress),
+    Pasted(PathBuf, PathBuf, Option<String>), // source, destination and error
+    PasteSkipped,
+    PasteConflict(PathBuf),                   // the destination that's taken
+    PasteDone,
+}
+
+enum PromptKind {
+    // the filter that was active before prompting is restored on cancel
+    Filter { kind: FilterKind, orig: Option<Filter> },
+    // the selection and search are restored on cancel
+    Search { orig_sel: usize, orig: Option<Search> },
+    Find,
+    // answered with a single y or n
+    Confirm(Confirmation),
+    // answered with a single key for the policy, uppercase for all
+    Conflict,
+    // the new name is edited in place of the old one
+    Rename { path: PathBuf, editor: LineEditor },
+    // the names of the marked entries in `dir` are rewritten by a pattern,
+    // the renames it makes are shown as it's typed
+    BatchRename { dir: PathBuf, old: Vec<OsString>, names: Vec<String>, renames: rename::Renames },
+    // the name of a new entry in `dir`, edited in the status line
+    Create { dir: PathBuf, new: ops::NewEntry, editor: LineEditor },
+}
+
+enum Confirmation {
+    Delete(Vec<PathBuf>),
+    // the new names are shown next to the old ones until answered
+    Rename(PathBuf, rename::Renames),
+}
+
+// The ncdu like mode, where the dirs under `root` are sorted by their
+// total size with bars showing how it's spread
+struct Usage {
+    root: PathBuf,
+    scanned: u64, // bytes counted so far
+    done: bool,
+    cancel: Arc<AtomicBool>,
+}
+
+// Entries yanked or cut, waiting to be pasted
+struct Clipboard {
+    paths: Vec<PathBuf>,
+    cut: bool, // whether they're moved instead of copied
+}
+
+// A paste running in the background
+struct Paste {
+    cut: bool,
+    progress: ops::Progress,
+    pasted: usize, // entries done without errors
+    skipped: usize,
+    dirs: HashSet<PathBuf>, // dirs changed by it, rescanned at the end
+    conflict: Option<PathBuf>, // taken destination waiting to be asked about
+    answers: Sender<Option<ops::Answer>>,
+    cancel: Arc<AtomicBool>,
+}
+
+// a line of user input being typed into the status line
+struct Prompt {
+    kind: PromptKind,
+    text: String,
+    error: Option<String>,
+}
+
+struct StatusLine {
+    geo: Rect,
+    text: String,
+    cursor: Option<usize>, // char index of the text cursor, if shown
+    error: Option<String>,
 }
 
 type ViewMap<'a> = HashMap<PathBuf, View>;
 type ColorMap<'a> = HashMap<&'a str, ObjectStyle>;
 type KeyBinds = HashMap<char, Action>;
+// info fields to show per column, by level offset from the current one
+type ColumnInfo = HashMap<isize, Vec<InfoField>>;
 
 struct Nv<'a> {
     root: RootWin,
@@ -109,8 +377,68 @@ struct Nv<'a> {
 
     views_shown: usize,
 
+    status: StatusLine,
+    prompt: Option<Prompt>,
+    // shown in the status line until the next key press
+    message: Option<String>,
+    error: Option<String>,
+
+    last_search: Option<Search>,
+
+    finder: Option<Finder>,
+    finders_started: usize,
+
+    events: Receiver<Event>,
+    event_sender: Sender<Event>,
+    // keys are left alone while other programs have the terminal
+    keys_paused: Arc<AtomicBool>,
+
+    watcher: Option<Watcher>,
+    // cached dirs that aren't watched anymore and may be out of date
+    unwatched: HashSet<PathBuf>,
+
+    // git status by repository root, None if git failed for it. Failed
+    // ones are only tried again when they change
+    git: HashMap<PathBuf, Option<Rc<RepoStatus>>>,
+    git_roots: HashMap<PathBuf, Option<PathBuf>>, // repository root by dir
+    git_fetching: HashSet<PathBuf>,
+    // repositories that changed while their status was being fetched
+    git_stale: HashSet<PathBuf>,
+
     colors: ColorMap<'a>,
     binds: KeyBinds,
+
+    column_info: ColumnInfo,
+    show_info: bool,
+
+    // entries excluded by ignore files are left out of the listings
+    hide_ignored: bool,
+
+    // computed dir sizes by path
+    dir_sizes: HashMap<PathBuf, u64>,
+    // dirs whose size is being computed
+    sizing: HashSet<PathBuf>,
+    size_jobs: Vec<Arc<AtomicBool>>,
+    // the sizes of the dirs in the current column are computed as it's shown
+    auto_sizes: bool,
+    // the user stopped the sizing, it's not restarted until something changes
+    sizes_cancelled: bool,
+    usage: Option<Usage>,
+    clipboard: Option<Clipboard>,
+    paste: Option<Paste>,
+    // where the trashed entries came from, loaded while the trash is shown
+    trash: Option<Rc<HashMap<OsString, trash::TrashInfo>>>,
+    // for the names taken when pasting, None asks every time
+    on_conflict: Option<ops::Policy>,
+
+    // where we were in each dir, this and previous sessions
+    positions: state::Positions,
+
+    // symlinked dirs are entered at their resolved location instead of
+    // where they were found, like `cd -P`
+    physical: bool,
+    // rows kept between the selection and the column edges
+    scroll_off: usize,
 }
 
 // == TRAITS ==
@@ -135,6 +463,7 @@ impl Rect {
 
 impl RootWin {
     fn new(geo: Rect) -> Self {
+        let cooked = term_modes();
         let screen = Screen::new(true);
         let term = Crossterm::from_screen(&screen);
 
@@ -142,6 +471,8 @@ impl RootWin {
             // stdout: MouseTerminal::from(std::io::stdout().into_raw_mode().unwrap()),
             term: term,
             screen: screen,
+            cooked: cooked,
+            raw: None,
 
 
             geo: geo,
@@ -149,6 +480,7 @@ impl RootWin {
         }
     }
 
+    #[allow(dead_code)]
     fn get_fullscreen_geo(&self) -> Rect {
         let mut r = self.geo.clone();
         r.x = 0;
@@ -188,6 +520,7 @@ impl RootWin {
         cursor::from_screen(&self.screen).goto(x, y).unwrap();
     }
 
+    #[allow(dead_code)]
     fn input<'a>(&'a self) -> TerminalInput<'a> {
         crossterm::input::from_screen(&self.screen)
         // self.term.input()
@@ -197,6 +530,25 @@ impl RootWin {
         cursor::from_screen(&self.screen)
     }
 
+    // Hands the terminal back the way it was found, for programs like
+    // editors to take over
+    fn suspend(&mut self) {
+        self.cursor().show().unwrap();
+        let _ = std::io::stdout().flush();
+
+        self.raw = term_modes();
+        if let Some(ref cooked) = self.cooked {
+            set_term_modes(cooked);
+        }
+    }
+
+    fn resume(&mut self) {
+        if let Some(ref raw) = self.raw {
+            set_term_modes(raw);
+        }
+        self.cursor().hide().unwrap();
+    }
+
     fn clear(&mut self) {
         self.draw_area = Some(self.geo.clone());
         self.draw_area.as_mut().unwrap().x = 0;
@@ -204,7 +556,7 @@ impl RootWin {
 
         for y in 0..self.geo.h {
             self.goto(0, y);
-            self.print(&format!("{: <1$}", "", self.geo.w as usize))
+            self.print(format!("{: <1$}", "", self.geo.w as usize))
         }
     }
 
@@ -255,62 +607,443 @@ impl Canvas for RootWin {
     }
 }
 
+impl Entry {
+    fn from_dir_entry(e: DirEntry) -> Self {
+        let path = e.path();
+        let file_type = e.file_type().ok();
+
+        let is_link = file_type.is_some_and(|ft| ft.is_symlink());
+
+        // Only symlinks need an extra stat to know what they point to
+        let (is_dir, is_file, broken_link) = match file_type {
+            Some(ft) if ft.is_symlink() => match path.metadata() {
+                Ok(meta) => (meta.is_dir(), meta.is_file(), false),
+                Err(_) => (false, false, true),
+            },
+            Some(ft) => (ft.is_dir(), ft.is_file(), false),
+            None => (false, false, false),
+        };
+
+        let link_target = match is_link {
+            true  => path.read_link().ok(),
+            false => None,
+        };
+
+        Self {
+            path: path,
+            file_type: file_type,
+            is_dir: is_dir,
+            is_file: is_file,
+            link_target: link_target,
+            broken_link: broken_link,
+            dir_size: None,
+            partial_size: None,
+            meta: OnceCell::new(),
+        }
+    }
+
+    fn meta(&self) -> Option<&Metadata> {
+        self.meta.get_or_init(|| self.path.symlink_metadata().ok()).as_ref()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.file_type.is_some_and(|ft| ft.is_symlink())
+    }
+
+    // Dirs count as empty until their size is computed
+    fn size(&self) -> u64 {
+        match self.is_dir && !self.is_symlink() {
+            true  => self.dir_size.unwrap_or(0),
+            false => self.meta().map_or(0, |m| m.len()),
+        }
+    }
+
+    // The most specific style there is for the entry, the way ls picks
+    // its `LS_COLORS`. Regular files are styled by their suffix before
+    // falling back to `File`
+    fn style<'c>(&self, c: &'c ColorMap) -> &'c ObjectStyle {
+        let mode = self.meta().map_or(0, |m| m.permissions().mode());
+        let ft = self.file_type;
+        let is = |f: fn(&FileType) -> bool| ft.as_ref().is_some_and(f);
+
+        let (sticky, other_w) = (mode & 0o1000 != 0, mode & 0o002 != 0);
+
+        let mut names: Vec<&str> = vec![];
+        if self.broken_link {
+            names.extend(["BrokenLink", "Symlink"]);
+        } else if self.is_symlink() {
+            names.push("Symlink");
+        } else if self.is_dir {
+            if sticky && other_w {
+                names.push("StickyOtherWritable");
+            }
+            if other_w {
+                names.push("OtherWritable");
+            }
+            if sticky {
+                names.push("Sticky");
+            }
+            names.push("Directory");
+        } else if is(FileType::is_fifo) {
+            names.push("Fifo");
+        } else if is(FileType::is_socket) {
+            names.push("Socket");
+        } else if is(FileType::is_block_device) {
+            names.push("BlockDevice");
+        } else if is(FileType::is_char_device) {
+            names.push("CharDevice");
+        } else {
+            if mode & 0o4000 != 0 {
+                names.push("Setuid");
+            }
+            if mode & 0o2000 != 0 {
+                names.push("Setgid");
+            }
+            if mode & 0o111 != 0 {
+                names.push("Executable");
+            }
+        }
+
+        if let Some(style) = names.iter().find_map(|name| c.get(name)) {
+            return style;
+        }
+
+        // Longest matching suffix first
+        if self.is_file {
+            let name = self.path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+            let suffix_style = name.char_indices()
+                .find_map(|(i, _)| c.get(format!("*{}", &name[i..]).as_str()));
+
+            if let Some(style) = suffix_style {
+                return style;
+            }
+        }
+
+        &c["File"]
+    }
+}
+
 impl DirView {
-    fn new<P: AsRef<Path>>(geo: Rect, dir: P) -> Self {
+    fn new<P: AsRef<Path>>(geo: Rect, dir: P, scroll_off: usize) -> Self {
         Self {
             geo: geo,
-            dir: std::fs::canonicalize(dir).unwrap(),
+            // kept as given, so symlinked dirs stay where they were entered
+            dir: dir.as_ref().to_owned(),
             sel: vec![0],
+            marks: vec![],
             scroll: 0,
+            scroll_off: scroll_off,
 
             entries: vec![],
             sorted_indices: vec![],
+            sort_order: SortOrder::Name,
+            filter: None,
+            search: None,
+            info: vec![],
+            git: None,
+            usage: false,
+            usage_total: None,
+            trash: None,
+            editing: None,
+            renames: None,
+
+            scan: None,
+            pending_sel: None,
+            pending_scroll: None,
+            error: None,
         }
     }
 
-    fn scan_dir(&mut self) {
-        self.entries = self.dir
-            .read_dir()
-            .unwrap()
-            .map(|e| e.unwrap().path())
-            .collect();
+    // Starts scanning the directory on a worker thread which streams the
+    // entries back as `Event::Scanned`. A first scan shows the entries as
+    // they come in while a rescan keeps the old ones until it's done.
+    // Entries excluded by ignore files are left out if `hide_ignored`
+    fn scan_dir(&mut self, events: Sender<Event>, hide_ignored: bool) {
+        static SCANS_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+        self.cancel_scan();
+
+        let id = SCANS_STARTED.fetch_add(1, Ordering::Relaxed);
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        self.scan = Some(Scan {
+            id: id,
+            cancel: cancel.clone(),
+            replace: !self.entries.is_empty(),
+            incoming: vec![],
+        });
+
+        let dir = self.dir.clone();
+
+        thread::spawn(move || {
+            let mut batch = vec![];
+            let mut last_send = Instant::now();
+
+            let read_dir = match dir.read_dir() {
+                Ok(read_dir) => read_dir,
+                Err(e) => {
+                    let _ = events.send(Event::ScanDone(dir, id, Some(io_error_string(&e))));
+                    return;
+                },
+            };
+
+            let kept = match hide_ignored {
+                true  => Some(git::unignored_names(&dir)),
+                false => None,
+            };
+
+            for e in read_dir {
+                if cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                if let Ok(e) = e {
+                    if kept.as_ref().is_none_or(|kept| kept.contains(&e.file_name())) {
+                        batch.push(Entry::from_dir_entry(e));
+                    }
+                }
+
+                if last_send.elapsed() >= SCAN_BATCH_INTERVAL {
+                    last_send = Instant::now();
+                    let batch = std::mem::take(&mut batch);
+                    if events.send(Event::Scanned(dir.clone(), id, batch)).is_err() {
+                        return;
+                    }
+                }
+            }
+
+            let _ = events.send(Event::Scanned(dir.clone(), id, batch));
+            let _ = events.send(Event::ScanDone(dir, id, None));
+        });
+    }
+
+    fn cancel_scan(&mut self) {
+        if let Some(scan) = self.scan.take() {
+            scan.cancel.store(true, Ordering::Relaxed);
+        }
+        self.pending_sel = None;
+        self.pending_scroll = None;
+    }
+
+    fn is_loading(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    // Takes in entries streamed by scan `id`
+    fn add_scanned(&mut self, id: usize, entries: Vec<Entry>) {
+        match self.scan {
+            Some(ref mut scan) if scan.id == id => {
+                if scan.replace {
+                    scan.incoming.extend(entries);
+                    return;
+                }
+            },
+            _ => return,
+        }
+
+        self.entries.extend(entries);
+        self.update_indices();
+
+        if let Some(name) = self.pending_sel.take() {
+            if self.select_by_name(&name) {
+                if let Some(scroll) = self.pending_scroll.take() {
+                    self.scroll = scroll;
+                }
+                self.ensure_sel_in_view();
+            }
+        }
+    }
+
+    // A failed scan leaves the view empty with the error to show
+    fn finish_scan(&mut self, id: usize, error: Option<String>) {
+        match self.scan {
+            Some(ref scan) if scan.id == id => (),
+            _ => return,
+        }
+
+        let scan = self.scan.take().unwrap();
+        match error {
+            Some(_) => self.replace_entries(vec![]),
+            None if scan.replace => self.replace_entries(scan.incoming),
+            None => (),
+        }
 
-        if self.entries.len() <= *self.sel.iter().max().unwrap_or(&0) {
-            self.sel = vec![0];
+        // Rescans only swap in their entries at the end
+        if let Some(name) = self.pending_sel.take() {
+            if self.select_by_name(&name) {
+                self.ensure_sel_in_view();
+            }
         }
 
-        self.sorted_indices = (0..self.entries.len()).collect();
+        self.error = error;
+        self.pending_scroll = None;
+    }
+
+    // Swaps in a new listing keeping the selections on the same entries by
+    // name. If the cursor's entry is gone, the cursor stays where it was
+    fn replace_entries(&mut self, entries: Vec<Entry>) {
+        let name = |i: usize| self.entries[i].path.file_name().map(|name| name.to_owned());
+        let cursor_name = self.sorted_indices.get(self.sel[0]).and_then(|&i| name(i));
+        let mark_names: Vec<OsString> = self.marks.iter().filter_map(|&i| name(i)).collect();
+        let cursor = self.sel[0];
+
+        self.entries = entries;
+        self.sorted_indices = vec![];
+        self.update_indices();
+
+        let entries = &self.entries;
+        let find = |name: &OsStr| entries.iter().position(|e| e.path.file_name() == Some(name));
+        self.marks = mark_names.iter().filter_map(|name| find(name)).collect();
+
+        let max_sel = self.sorted_indices.len().saturating_sub(1);
+        let position = cursor_name
+            .and_then(|name| find(&name))
+            .and_then(|abs_i| self.sorted_indices.iter().position(|&i| i == abs_i));
+        self.sel = vec![position.unwrap_or(cursor.min(max_sel))];
+        self.ensure_sel_in_view();
     }
 
     fn sort(&mut self, by: SortOrder) {
-        // Temporarily restore the selections to absolute indices
-        for sel in self.sel.iter_mut() { 
-            *sel = self.sorted_indices[*sel];
+        self.sort_order = by;
+        self.update_indices();
+    }
+
+    fn set_dir_size(&mut self, path: &Path, size: u64) {
+        let entry = match self.entries.iter_mut().find(|e| e.path == path) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.dir_size = Some(size);
+        entry.partial_size = None;
+        self.usage_total = None;
+
+        if let SortOrder::Size = self.sort_order {
+            self.update_indices();
         }
+    }
 
-        match by {
-            SortOrder::Name => {
-                let entries = &self.entries;
-                self.sorted_indices
-                    .sort_by(|a, b| {
-                        let aname = entries[*a].file_name();
-                        let bname = entries[*b].file_name();
-                        aname.cmp(&bname)
-                    });
-            },
+    // Only shown, the entry isn't sorted by it and counts as unsized until
+    // the computation is done
+    fn set_partial_size(&mut self, path: &Path, size: u64) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.path == path) {
+            entry.partial_size = Some(size);
+        }
+    }
+
+    // Forgets the size of the entry at `path`, it's out of date
+    fn clear_dir_size(&mut self, path: &Path) {
+        let entry = match self.entries.iter_mut().find(|e| e.path == path) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if entry.dir_size.take().is_none() {
+            return;
+        }
+        self.usage_total = None;
+
+        if let SortOrder::Size = self.sort_order {
+            self.update_indices();
+        }
+    }
+
+    fn clear_partial_sizes(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.partial_size = None;
+        }
+    }
+
+    // Fills in the computed sizes of the entries
+    fn apply_dir_sizes(&mut self, sizes: &HashMap<PathBuf, u64>) {
+        let mut changed = false;
+        for entry in self.entries.iter_mut() {
+            if let Some(&size) = sizes.get(&entry.path) {
+                changed |= entry.dir_size != Some(size);
+                entry.dir_size = Some(size);
+            }
+        }
+        if changed {
+            self.usage_total = None;
+        }
+
+        if let (true, SortOrder::Size) = (changed, self.sort_order) {
+            self.update_indices();
         }
+    }
+
+    // Dirs whose size isn't known yet, symlinked ones aren't counted
+    fn unsized_dirs(&self) -> Vec<PathBuf> {
+        self.entries.iter()
+            .filter(|e| e.is_dir && !e.is_symlink() && e.dir_size.is_none())
+            .map(|e| e.path.clone())
+            .collect()
+    }
+
+    fn set_filter(&mut self, filter: Option<Filter>) {
+        self.filter = filter;
+        self.update_indices();
+    }
+
+    // Rebuilds the sorted indices from scratch, honoring the sort order and
+    // the filter while keeping the selections on the same entries
+    fn update_indices(&mut self) {
+        // The entries may have changed as well
+        self.usage_total = None;
+
+        // Temporarily restore the cursor to an absolute index
+        let cursor = self.sel[0];
+        let abs_cursor = self.sorted_indices.get(cursor).cloned();
+
+        let mut indices: Vec<usize> = (0..self.entries.len()).collect();
+        self.sort_indices(&mut indices);
+
+        if let Some(ref filter) = self.filter {
+            let entries = &self.entries;
+            indices.retain(|&i| {
+                entries[i].path.file_name()
+                          .is_some_and(|n| filter.is_match(&display_name(n)))
+            });
+        }
+
+        self.sorted_indices = indices;
 
-        // Restore the selections to sorted indices
-        for sel in self.sel.iter_mut() { 
-            // This unwrap should be safe as we only reordered the indices
-            *sel = self.sorted_indices.iter().position(|&i| i == *sel).unwrap();
+        // Back to a sorted index. If the entry was filtered out the cursor
+        // stays where it was, the marks don't depend on it
+        let max_sel = self.sorted_indices.len().saturating_sub(1);
+        let position = abs_cursor.and_then(|abs_i| self.sorted_indices.iter().position(|&i| i == abs_i));
+        self.sel = vec![position.unwrap_or(cursor.min(max_sel))];
+
+        self.ensure_sel_in_view();
+    }
+
+    // Sorts indices into `entries` in the sort order
+    fn sort_indices(&self, indices: &mut [usize]) {
+        let entries = &self.entries;
+
+        match self.sort_order {
+            SortOrder::Name => {
+                indices.sort_by(|a, b| {
+                    let aname = entries[*a].path.file_name();
+                    let bname = entries[*b].path.file_name();
+                    aname.cmp(&bname)
+                });
+            },
+            SortOrder::Size => {
+                indices.sort_by(|a, b| {
+                    entries[*b].size().cmp(&entries[*a].size())
+                        .then_with(|| entries[*a].path.file_name().cmp(&entries[*b].path.file_name()))
+                });
+            },
         }
     }
 
     fn inc_sel(&mut self, ofs: isize) -> isize {
         use std::cmp::{min, max};
 
-        let max_sel   = self.entries.len() -1;
+        if self.sorted_indices.is_empty() {
+            return 0;
+        }
+
+        let max_sel   = self.sorted_indices.len() -1;
         let old_index = self.sel[0];
 
         self.sel[0] = min(max(0, self.sel[0] as isize + ofs) as usize, max_sel);
@@ -318,41 +1051,110 @@ impl DirView {
         return self.sel[0] as isize - old_index as isize;
     }
 
+    // Moves the selection to sorted index `index`, or as close as it gets.
+    // Returns how much it moved
+    fn set_sel(&mut self, index: usize) -> isize {
+        let ofs = index as isize - self.sel[0] as isize;
+        return self.inc_sel(ofs);
+    }
+
+    // Moves the selection and the scroll together by `ofs` so the selection
+    // stays on the same row, as far as the entries go.
+    // Returns how much the selection moved
+    fn scroll_sel(&mut self, ofs: isize) -> isize {
+        use std::cmp::{min, max};
+
+        let moved = self.inc_sel(ofs);
+
+        let max_scroll = self.sorted_indices.len().saturating_sub(self.geo.h as usize);
+        let scroll     = max(0, self.scroll as isize + ofs) as usize;
+
+        self.scroll = match ofs > 0 {
+            true  => min(scroll, max(max_scroll, self.scroll)),
+            false => scroll,
+        };
+        self.ensure_sel_in_view();
+
+        return moved;
+    }
+
+    // The sorted index of the entry in the middle of the shown rows
+    fn middle_index(&self) -> usize {
+        let shown = self.sorted_indices.len()
+            .saturating_sub(self.scroll)
+            .min(self.geo.h as usize);
+
+        return self.scroll + shown.saturating_sub(1) / 2;
+    }
+
+    // Scrolls so the selection is at least `scroll_off` rows away from the
+    // edges, unless it's that close to the first or last entry. The margin
+    // shrinks to fit short columns
     fn ensure_sel_in_view(&mut self) {
-        let sel_y: isize = 
-            self.sel[0] as isize -
-            self.scroll as isize;
+        use std::cmp::min;
+
+        let h = self.geo.h as usize;
+        if h == 0 {
+            return;
+        }
+
+        let off    = min(self.scroll_off, (h - 1) / 2);
+        let top    = self.sel[0].saturating_sub(off);
+        let bottom = min(self.sel[0] + off, self.sorted_indices.len().saturating_sub(1));
 
-        if sel_y >= self.geo.h as isize {
-            self.scroll += (sel_y - (self.geo.h as isize) + 1) as usize;
+        if top < self.scroll {
+            self.scroll = top;
 
-        } else if sel_y < 0 {
-            self.scroll -= -sel_y as usize;
+        } else if bottom >= self.scroll + h {
+            self.scroll = bottom + 1 - h;
         }
     }
 
+    // Moves the selection to the first entry matching `search` starting at
+    // sorted index `from` and wrapping around the ends.
+    // Returns whether anything matched
+    fn select_match(&mut self, search: &Search, from: usize, forward: bool) -> bool {
+        let n = self.sorted_indices.len();
+
+        for k in 0..n {
+            let i = match forward {
+                true  => (from + k) % n,
+                false => (from + n - k % n) % n,
+            };
+
+            if search.is_match(&self.entry_display_name(self.sorted_indices[i])) {
+                self.sel[0] = i;
+                self.ensure_sel_in_view();
+
+                return true;
+            }
+        }
+
+        false
+    }
+
     fn make_selected_dir_view(&self) -> Option<Self> {
-        let dir = &self.sel_path();
+        let entry = self.sel_entry()?;
 
-        if dir.is_dir() {
-            Some(Self::new(self.geo.clone(), dir.clone()))
+        if entry.is_dir {
+            Some(Self::new(self.geo.clone(), &entry.path, self.scroll_off))
         } else {
             None
         }
     }
 
     fn make_selected_file_view(&self) -> Option<FileView> {
-        let file = &self.sel_path();
+        let entry = self.sel_entry()?;
 
-        if file.is_file() {
-            Some(FileView::new(self.geo.clone(), file.clone()))
+        if entry.is_file {
+            Some(FileView::new(self.geo.clone(), &entry.path))
         } else {
             None
         }
     }
 
     fn make_selected_view(&self) -> Option<View> {
-        if self.sel_path().is_dir() {
+        if self.sel_entry()?.is_dir {
             self.make_selected_dir_view().map(|dv| dv.into())
         } else {
             self.make_selected_file_view().map(|fv| fv.into())
@@ -361,10 +1163,27 @@ impl DirView {
 
     fn make_parent_dir_view(&self) -> Option<Self> {
         self.dir.parent().map(|dir|{
-            Self::new(self.geo.clone(), dir.to_owned())
+            Self::new(self.geo.clone(), dir, self.scroll_off)
         })
     }
 
+    // Points the entries at their new names all at once, so swapped names
+    // don't get mixed up, keeping the selections on them
+    fn rename_entries(&mut self, renames: &[(OsString, OsString)]) {
+        for entry in self.entries.iter_mut() {
+            let new = renames.iter()
+                .find(|(old, _)| entry.path.file_name() == Some(old.as_os_str()))
+                .map(|(_, new)| new);
+
+            if let Some(new) = new {
+                entry.path.set_file_name(new);
+                entry.meta = OnceCell::new();
+            }
+        }
+
+        self.update_indices();
+    }
+
     fn select_first(&mut self) {
         if  self.sel.len() > 0 &&
             self.sorted_indices.len() > 0 {
@@ -373,48 +1192,117 @@ impl DirView {
         }
     }
 
-    fn select_by_name(&mut self, name: String) {
+    // If the entry wasn't scanned yet, it gets selected once it is
+    fn select_by_name(&mut self, name: &OsStr) -> bool {
         if self.sel.len() > 0 {
             let sorted_i = self.sorted_indices.iter()
-                               .position(|&abs_i| {
-                                   self.entries[abs_i].file_name().unwrap()
-                                                      .to_str().unwrap()
-                                                      .to_owned() 
-                                                      == name
-                               });
+                               .position(|&abs_i| self.entry_file_name(abs_i) == name);
             if let Some(sorted_i) = sorted_i {
                 self.sel[0] = sorted_i;
+                return true;
             }
         }
+
+        if self.is_loading() {
+            self.pending_sel = Some(name.to_owned());
+        }
+        return false;
+    }
+
+    // Selects `name` with the view scrolled like it was when it was left
+    fn restore_position(&mut self, name: &OsStr, scroll: usize) {
+        match self.select_by_name(name) {
+            true  => {
+                self.scroll = scroll;
+                self.ensure_sel_in_view();
+            },
+            false => self.pending_scroll = Some(scroll),
+        }
     }
 
     fn dir_path(&self) -> &Path {
         &self.dir
     }
 
-    fn dir_file_name(&self) -> String {
-        self.dir.file_name().unwrap()
-                .to_str().unwrap()
-                .to_owned()
+    // The root dir has no name of its own
+    fn dir_file_name(&self) -> &OsStr {
+        self.dir.file_name().unwrap_or(self.dir.as_os_str())
+    }
+
+    // Marks the entry under the cursor, or unmarks it if it already was
+    fn toggle_mark(&mut self) {
+        if self.sorted_indices.is_empty() {
+            return;
+        }
+
+        let cursor = self.sorted_indices[self.sel[0]];
+        match self.marks.iter().position(|&i| i == cursor) {
+            Some(i) => { self.marks.remove(i); },
+            None => self.marks.push(cursor),
+        }
+    }
+
+    fn clear_marks(&mut self) {
+        self.marks.clear();
+    }
+
+    // Filtered out entries stay marked
+    fn mark_count(&self) -> usize {
+        self.marks.len()
+    }
+
+    // The paths of the marked entries, or of the one under the cursor if
+    // none are. In the order they're listed, not the order they were marked
+    // in, hidden ones included
+    fn marked_paths(&self) -> Vec<PathBuf> {
+        let mut marked = match self.marks.is_empty() {
+            true  => self.sorted_indices.get(self.sel[0]).cloned().into_iter().collect(),
+            false => self.marks.clone(),
+        };
+        self.sort_indices(&mut marked);
+
+        marked.iter()
+            .map(|&i| self.entry_path(i).to_owned())
+            .collect()
+    }
+
+    fn sel_entry(&self) -> Option<&Entry> {
+        Some(&self.entries[*self.sorted_indices.get(self.sel[0])?])
     }
 
-    fn sel_path(&self) -> &Path {
-        self.entry_path(self.sorted_indices[self.sel[0]])
+    fn sel_path(&self) -> Option<&Path> {
+        Some(self.entry_path(*self.sorted_indices.get(self.sel[0])?))
     }
 
-    fn sel_file_name(&self) -> String {
-        self.entry_file_name(self.sorted_indices[self.sel[0]])
+    fn sel_file_name(&self) -> Option<&OsStr> {
+        Some(self.entry_file_name(*self.sorted_indices.get(self.sel[0])?))
     }
 
     fn entry_path(&self, i: usize) -> &Path {
-        &self.entries[i]
+        &self.entries[i].path
     }
 
-    fn entry_file_name(&self, i: usize) -> String { 
-        self.entry_path(i)
-            .file_name().unwrap()
-            .to_str().unwrap()
-            .to_owned()
+    fn entry_file_name(&self, i: usize) -> &OsStr {
+        let path = self.entry_path(i);
+        path.file_name().unwrap_or(path.as_os_str())
+    }
+
+    // The name as drawn and matched by filters and searches
+    fn entry_display_name(&self, i: usize) -> String {
+        display_name(self.entry_file_name(i))
+    }
+
+    // The formatted info fields of entry `i`, empty if it can't be stat'ed
+    fn entry_info(&self, i: usize) -> Vec<String> {
+        let entry = &self.entries[i];
+
+        match entry.meta() {
+            Some(meta) => self.info.iter().map(|f| match (f, entry.dir_size.or(entry.partial_size)) {
+                (InfoField::Size, Some(size)) => info::human_size(size),
+                _ => f.format(meta),
+            }).collect(),
+            None => vec![String::new(); self.info.len()],
+        }
     }
 
     fn entry_count(&self) -> usize {
@@ -428,64 +1316,561 @@ trait StrUtils {
 
 impl StrUtils for String {
     fn ellipsize(&mut self, len: usize) {
-        if self.len() > len {
-            self.truncate(len - 1);
-            self.push('…');
+        if self.chars().count() > len {
+            *self = self.chars().take(len.saturating_sub(1)).collect();
+            if len > 0 {
+                self.push('…');
+            }
         }
     }
 }
 
-impl<'a> Drawable<&ColorMap<'a>> for DirView {
-    fn get_geo(&self) -> Rect {
-        self.geo.clone()
-    }
-
-    fn draw(&mut self, d: &mut impl Canvas, c: &ColorMap) {
-        if self.dir.is_dir() {
-
-            let dir_iiter = self.sorted_indices.iter().skip(self.scroll);
-
-            for (i, de) in (0..self.geo.h).zip(dir_iiter) {
-
-                d.goto(0, i);
+impl Search {
+    // Smart case: the search is case insensitive unless the pattern
+    // contains an uppercase letter
+    fn new(pattern: &str) -> Self {
+        let mut re = regex::escape(pattern);
+        if !pattern.chars().any(char::is_uppercase) {
+            re.insert_str(0, "(?i)");
+        }
 
-                // Plain item
-                let mut fname = self.entry_file_name(*de);
-                fname.ellipsize(self.geo.w as usize);
+        Self {
+            // An escaped pattern is always valid
+            re: Regex::new(&re).unwrap(),
+        }
+    }
 
-                let w = self.geo.w as usize;
-                let p = format!("{0: <1$}", &fname, w);
+    fn is_match(&self, name: &str) -> bool {
+        self.re.is_match(name)
+    }
 
-                // Apply Styles
-                let s = match self.entries[*de].is_file() {
-                    true  => "File",
-                    false => "Directory",
-                };
-                let p = c[s].apply_to(p);
+    // byte range of the first match in `name`
+    fn find(&self, name: &str) -> Option<(usize, usize)> {
+        self.re.find(name).map(|m| (m.start(), m.end()))
+    }
+}
 
-                if self.sel.contains(&(self.scroll + i as usize)) {
-                    let p = c["Selected"].apply_to(p);
-                    d.print(p);
-                } else {
-                    d.print(p);
-                }
+impl FilterKind {
+    fn next(self) -> Self {
+        match self {
+            FilterKind::Substring => FilterKind::Glob,
+            FilterKind::Glob      => FilterKind::Regex,
+            FilterKind::Regex     => FilterKind::Substring,
+        }
+    }
 
-            }
+    fn name(self) -> &'static str {
+        match self {
+            FilterKind::Substring => "substring",
+            FilterKind::Glob      => "glob",
+            FilterKind::Regex     => "regex",
         }
     }
 }
 
+impl Filter {
+    fn new(kind: FilterKind, pattern: &str) -> std::result::Result<Self, String> {
+        let re = match kind {
+            FilterKind::Substring => regex::escape(pattern),
+            FilterKind::Glob      => glob_to_regex(pattern),
+            FilterKind::Regex     => pattern.to_owned(),
+        };
+
+        Regex::new(&re)
+            .map(|re| Self {
+                kind: kind,
+                pattern: pattern.to_owned(),
+                re: re,
+            })
+            // regex errors span several lines, only keep the actual message
+            .map_err(|e| {
+                e.to_string().lines().last().unwrap_or("").trim().to_owned()
+            })
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        self.re.is_match(name)
+    }
+}
+
+// Makes `path` absolute without resolving symlinks, starting from $PWD when
+// it still refers to the current dir like the shell does
+fn logical_path(path: &Path) -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let base = match std::env::var_os("PWD").map(PathBuf::from) {
+        Some(pwd) if pwd.is_absolute() && pwd.canonicalize().ok() == cwd.canonicalize().ok() => pwd,
+        _ => cwd,
+    };
+
+    let mut abs = PathBuf::new();
+    for comp in base.join(path).components() {
+        match comp {
+            Component::CurDir => (),
+            Component::ParentDir => { abs.pop(); },
+            comp => abs.push(comp),
+        }
+    }
+    abs
+}
+
+// Names are shown as UTF-8 with the invalid bytes escaped as `\xff` and
+// the control chars escaped so they can't mess with the terminal
+fn display_name(name: &OsStr) -> String {
+    let mut s = String::new();
+
+    for chunk in name.as_bytes().utf8_chunks() {
+        s.push_str(&escape_controls(chunk.valid()));
+        for b in chunk.invalid() {
+            s.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    s
+}
+
+// ESC -> "^[", DEL -> "^?", the C1 controls -> "\x9b"
+fn escape_controls(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c as u32 {
+            n @ 0x00..=0x1f => {
+                s.push('^');
+                s.push((n as u8 + b'@') as char);
+            },
+            0x7f => s.push_str("^?"),
+            n @ 0x80..=0x9f => s.push_str(&format!("\\x{:02x}", n)),
+            _ => s.push(c),
+        }
+    }
+    s
+}
+
+// "Permission denied (os error 13)" -> "permission denied"
+fn io_error_string(e: &std::io::Error) -> String {
+    let msg = e.to_string();
+    let msg = match msg.find(" (os error") {
+        Some(end) => &msg[..end],
+        None => &msg[..],
+    };
+
+    let mut chars = msg.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// A fresh empty dir for a test to work in, deleted when dropped, even if
+// the test fails. `name` has to be unique among the tests
+#[cfg(test)]
+struct TestDir(PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    fn new(name: &str) -> Self {
+        Self::new_in(&std::env::temp_dir(), name)
+    }
+
+    // In `base` instead of the temp dir
+    fn new_in(base: &Path, name: &str) -> Self {
+        let dir = base.join(format!("nv-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Why `name` can't be the new name of the entry at `path`, if it can't
+fn validate_name(path: &Path, name: &str) -> std::result::Result<(), String> {
+    rename::check_name(name)?;
+
+    let new = path.with_file_name(name);
+    if new != path && new.symlink_metadata().is_ok() {
+        return Err(format!("{} already exists", display_name(OsStr::new(name))));
+    }
+    Ok(())
+}
+
+// Why `name` can't be that of the `new` entry in `dir`, if it can't. Dirs
+// can be given with their parents
+fn validate_new_name(dir: &Path, name: &str, new: &ops::NewEntry) -> std::result::Result<(), String> {
+    let name = match new {
+        ops::NewEntry::Dir => {
+            for part in name.trim_end_matches('/').split('/') {
+                rename::check_name(part)?;
+            }
+            name.trim_end_matches('/')
+        },
+        _ => {
+            rename::check_name(name)?;
+            name
+        },
+    };
+
+    if dir.join(name).symlink_metadata().is_ok() {
+        return Err(format!("{} already exists", display_name(OsStr::new(name))));
+    }
+    Ok(())
+}
+
+// Draws `editor` as a text field `w` wide, scrolled to keep the cursor in
+// sight
+fn draw_editor(d: &mut impl Canvas, c: &ColorMap, editor: &LineEditor, w: usize) {
+    if w == 0 {
+        return;
+    }
+
+    // Control characters would mess up the terminal
+    let chars: Vec<char> = editor.text().chars()
+        .map(|ch| if ch.is_control() { '?' } else { ch })
+        .collect();
+    let start = (editor.cursor() + 1).saturating_sub(w);
+    let at = editor.cursor() - start;
+
+    let before: String = chars[start..editor.cursor()].iter().collect();
+    let under = chars.get(editor.cursor()).cloned().unwrap_or(' ');
+    let after: String = chars.iter().skip(editor.cursor() + 1).take(w - at - 1).collect();
+
+    d.print(c["Edit"].apply_to(before));
+    d.print(c["Selected"].apply_to(under));
+    d.print(c["Edit"].apply_to(format!("{0: <1$}", after, w - at - 1)));
+}
+
+// Shows why a view has nothing to show on its first row
+fn draw_error(d: &mut impl Canvas, c: &ColorMap, w: usize, error: &str) {
+    let mut text = error.to_owned();
+    text.ellipsize(w);
+
+    d.goto(0, 0);
+    d.print(c["Error"].apply_to(format!("{0: <1$}", text, w)));
+}
+
+// Translates a shell glob (`*`, `?`, `[...]` and `[!...]`) into an anchored
+// regex pattern
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = glob.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '[' => {
+                let rest = chars.as_str();
+
+                match rest.find(']') {
+                    Some(end) if end > 0 => {
+                        let mut class = &rest[..end];
+
+                        re.push('[');
+                        if class.starts_with('!') {
+                            re.push('^');
+                            class = &class[1..];
+                        }
+                        for c in class.chars() {
+                            if c == '\\' || c == '[' {
+                                re.push('\\');
+                            }
+                            re.push(c);
+                        }
+                        re.push(']');
+
+                        chars = rest[end+1..].chars();
+                    },
+                    _ => re.push_str("\\["),
+                }
+            },
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    re.push('$');
+    re
+}
+
+impl<'a> Drawable<&ColorMap<'a>> for DirView {
+    fn get_geo(&self) -> Rect {
+        self.geo.clone()
+    }
+
+    fn draw(&mut self, d: &mut impl Canvas, c: &ColorMap) {
+        let w = self.geo.w as usize;
+
+        let rows: Vec<usize> = self.sorted_indices.iter()
+            .skip(self.scroll)
+            .take(self.geo.h as usize)
+            .cloned()
+            .collect();
+
+        // Gather the info of all the shown rows to align it
+        let info: Vec<Vec<String>> = rows.iter()
+            .map(|&de| self.entry_info(de))
+            .collect();
+        let widths: Vec<usize> = (0..self.info.len())
+            .map(|f| info.iter().map(|r| r[f].chars().count()).max().unwrap_or(0))
+            .collect();
+
+        // Git status markers go in front of the names
+        let git_w = match self.git.is_some() && w >= MIN_NAME_WIDTH + 2 {
+            true  => 2,
+            false => 0,
+        };
+
+        // "[####    ]  50.0% " in front of the names in disk usage mode
+        let usage_w = match self.usage && w >= MIN_NAME_WIDTH + git_w + USAGE_BAR_WIDTH + 9 {
+            true  => USAGE_BAR_WIDTH + 9,
+            false => 0,
+        };
+        // Adding up the sizes stats every entry, only done when the bars
+        // are shown and kept until the entries change
+        let total = match (usage_w, self.usage_total) {
+            (0, _) => 0,
+            (_, Some(total)) => total,
+            (_, None) => {
+                let total = self.entries.iter().map(|e| e.size()).sum();
+                self.usage_total = Some(total);
+                total
+            },
+        };
+
+        // Drop the last fields until the names get enough room
+        let info_width = |n: usize| widths[..n].iter().map(|w| w + 1).sum::<usize>();
+        let mut nfields = self.info.len();
+        while nfields > 0 && info_width(nfields) + git_w + usage_w + MIN_NAME_WIDTH > w {
+            nfields -= 1;
+        }
+        let name_w = w - info_width(nfields) - git_w - usage_w;
+
+        for (i, (de, row_info)) in (0..self.geo.h).zip(rows.iter().zip(info.iter())) {
+
+            d.goto(0, i);
+
+            let entry = &self.entries[*de];
+            // The cursor stands out over the marks
+            let row = self.scroll + i as usize;
+            let highlight = match (self.sel[0] == row, self.marks.contains(de)) {
+                (true, _)      => Some(&c["Selected"]),
+                (false, true)  => Some(&c["Marked"]),
+                (false, false) => None,
+            };
+
+            if git_w > 0 {
+                let status = self.git.as_ref().and_then(|git| git.get(&entry.path));
+                let (marker, gs) = match status {
+                    Some(fs) => (format!("{} ", fs.marker()), &c[fs.style_name()]),
+                    None => (String::from("  "), &c["File"]),
+                };
+
+                match highlight {
+                    Some(hs) => d.print(hs.apply_to(gs.apply_to(marker))),
+                    None     => d.print(gs.apply_to(marker)),
+                }
+            }
+
+            if usage_w > 0 {
+                let share = match total {
+                    0 => 0.0,
+                    _ => entry.size() as f64 / total as f64,
+                };
+                let filled = (share * USAGE_BAR_WIDTH as f64).round() as usize;
+                let bar = format!(
+                    "[{0: <1$}] {2:>5.1}% ",
+                    "#".repeat(filled), USAGE_BAR_WIDTH, share * 100.0
+                );
+
+                match highlight {
+                    Some(hs) => d.print(hs.apply_to(c["UsageBar"].apply_to(bar))),
+                    None     => d.print(c["UsageBar"].apply_to(bar)),
+                }
+            }
+
+            // The cursor's name turns into a text field while it's renamed
+            let editor = self.editing.as_ref().filter(|_| row == self.sel[0]);
+            if let Some(editor) = editor {
+                draw_editor(d, c, editor, name_w);
+            } else {
+                // Plain item, symlinks followed by their target and trashed
+                // entries by where they came from
+                let mut fname = self.entry_display_name(*de);
+                let name_len = fname.len();
+                if let Some(ref target) = entry.link_target {
+                    fname.push_str(" -> ");
+                    fname.push_str(&display_name(target.as_os_str()));
+                }
+                let origin = self.trash.as_ref()
+                    .and_then(|trash| trash.get(self.entry_file_name(*de)));
+                if let Some(info) = origin {
+                    fname.push_str(" <- ");
+                    fname.push_str(&display_name(info.path.as_os_str()));
+                }
+                let new_name = self.renames.as_ref()
+                    .and_then(|renames| renames.iter().find(|(old, _)| old == self.entry_file_name(*de)));
+                if let Some((_, new)) = new_name {
+                    fname.push_str(" => ");
+                    fname.push_str(&display_name(new));
+                }
+                fname.ellipsize(name_w);
+
+                let p = format!("{0: <1$}", &fname, name_w);
+
+                // Split out the search match to be highlighted, only searched
+                // names are matched
+                let found = self.search.as_ref()
+                    .and_then(|s| s.find(&fname))
+                    .filter(|&(_, end)| end <= name_len);
+                let (p, m, rest) = match found {
+                    Some((start, end)) => (
+                        p[..start].to_owned(),
+                        p[start..end].to_owned(),
+                        p[end..].to_owned(),
+                    ),
+                    None => (p, String::new(), String::new()),
+                };
+
+                // Apply Styles
+                let s = entry.style(c);
+
+                for (seg, is_match) in [(p, false), (m, true), (rest, false)].iter() {
+                    if seg.is_empty() {
+                        continue;
+                    }
+
+                    // The innermost style takes precedence
+                    match (highlight, is_match) {
+                        (Some(hs), true) => d.print(hs.apply_to(
+                            s.apply_to(c["SearchMatch"].apply_to(seg))
+                        )),
+                        (Some(hs), false) => d.print(hs.apply_to(s.apply_to(seg))),
+                        (None, true) => d.print(s.apply_to(c["SearchMatch"].apply_to(seg))),
+                        (None, false) => d.print(s.apply_to(seg)),
+                    }
+                }
+            }
+
+            // Right aligned info fields
+            if nfields > 0 {
+                let info: String = row_info[..nfields].iter()
+                    .zip(widths.iter())
+                    .map(|(field, fw)| format!(" {0: >1$}", field, fw))
+                    .collect();
+
+                match highlight {
+                    Some(hs) => d.print(hs.apply_to(c["Info"].apply_to(info))),
+                    None     => d.print(c["Info"].apply_to(info)),
+                }
+            }
+
+        }
+
+        if let Some(ref error) = self.error {
+            draw_error(d, c, w, error);
+        }
+
+        // Mark the row after the last entry while the scan is running
+        let shown = self.sorted_indices.len().saturating_sub(self.scroll);
+        if self.is_loading() && shown < self.geo.h as usize {
+            let mut text = String::from("loading…");
+            text.ellipsize(w);
+
+            d.goto(0, shown as u16);
+            d.print(c["Info"].apply_to(format!("{0: <1$}", text, w)));
+        }
+    }
+}
+
+impl StatusLine {
+    fn new(geo: Rect) -> Self {
+        Self {
+            geo: geo,
+            text: String::new(),
+            cursor: None,
+            error: None,
+        }
+    }
+}
+
+impl<'a> Drawable<&ColorMap<'a>> for StatusLine {
+    fn get_geo(&self) -> Rect {
+        self.geo.clone()
+    }
+
+    fn draw(&mut self, d: &mut impl Canvas, c: &ColorMap) {
+        let w = self.geo.w as usize;
+
+        let mut text = self.text.clone();
+        if self.cursor.is_some() {
+            // room for the cursor at the end of the line
+            text.push(' ');
+        }
+        text.ellipsize(w);
+
+        let mut err = match self.error {
+            Some(ref err) => format!(" {}", err),
+            None => String::new(),
+        };
+        err.ellipsize(w - text.chars().count());
+        let err_len = err.chars().count();
+
+        d.goto(0, 0);
+
+        match self.cursor {
+            Some(cursor) if cursor < text.chars().count() => {
+                let before: String = text.chars().take(cursor).collect();
+                let under = text.chars().nth(cursor).unwrap();
+                let after: String = text.chars().skip(cursor + 1).collect();
+
+                d.print(c["StatusLine"].apply_to(&before));
+                d.print(c["Selected"].apply_to(under));
+                d.print(c["StatusLine"].apply_to(&after));
+            },
+            _ => d.print(c["StatusLine"].apply_to(&text)),
+        }
+
+        d.print(c["Error"].apply_to(&err));
+
+        let pad = w - text.chars().count() - err_len;
+        d.print(c["StatusLine"].apply_to(format!("{0: <1$}", "", pad)));
+    }
+}
+
 impl FileView {
     fn new<P: AsRef<Path>>(geo: Rect, path: P) -> Self {
-        let mut file = File::open(path).unwrap();
-        let buffer = file.by_ref().take()
+        let mut buffer = vec![];
+
+        let error = File::open(&path)
+            .and_then(|file| file.take(FILE_PREVIEW_LEN).read_to_end(&mut buffer))
+            .err()
+            .map(|e| io_error_string(&e));
 
         Self {
             geo: geo,
             path: path.as_ref().to_owned(),
-            file: file,
             buffer: buffer,
             scroll: 0,
+            error: error,
         }
     }
 
@@ -493,15 +1878,13 @@ impl FileView {
         &self.path
     }
 
-    fn file_name(&self) -> String {
-        self.path.file_name().unwrap()
-                 .to_str().unwrap()
-                 .to_owned()
+    fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or(self.path.as_os_str())
     }
 
-    fn make_parent_dir_view(&self) -> Option<DirView> {
+    fn make_parent_dir_view(&self, scroll_off: usize) -> Option<DirView> {
         self.path.parent().map(|dir|{
-            DirView::new(self.geo.clone(), dir.to_owned())
+            DirView::new(self.geo.clone(), dir, scroll_off)
         })
     }
 }
@@ -512,15 +1895,33 @@ impl<'a> Drawable<&ColorMap<'a>> for FileView {
     }
 
     fn draw(&mut self, d: &mut impl Canvas, c: &ColorMap) {
+        let w = self.geo.w as usize;
+
+        if let Some(ref error) = self.error {
+            draw_error(d, c, w, error);
+            return;
+        }
 
+        // The content is untrusted, nothing but escaped text gets through
+        let text = String::from_utf8_lossy(&self.buffer);
+        let lines = text.lines().skip(self.scroll);
+
+        for (y, line) in (0..self.geo.h).zip(lines) {
+            let mut line = escape_controls(&line.replace('\t', "    "));
+            line.ellipsize(w);
+
+            d.goto(0, y);
+            d.print(c["File"].apply_to(format!("{0: <1$}", line, w)));
+        }
     }
 }
 
 impl View {
-    fn make_parent_dir_view(&self) -> Option<DirView> {
+    // File views don't scroll like dirs, so they're told how the parent does
+    fn make_parent_dir_view(&self, scroll_off: usize) -> Option<DirView> {
         match self {
             View::Dir(ref dv) => dv.make_parent_dir_view(),
-            View::File(ref fv) => fv.make_parent_dir_view(),
+            View::File(ref fv) => fv.make_parent_dir_view(scroll_off),
         }
     }
 
@@ -531,209 +1932,1968 @@ impl View {
         }
     }
 
-    fn file_name(&self) -> String {
-        match self {
-            View::Dir(ref dv) => dv.dir_file_name(),
-            View::File(ref fv) => fv.file_name(),
+    fn file_name(&self) -> &OsStr {
+        match self {
+            View::Dir(ref dv) => dv.dir_file_name(),
+            View::File(ref fv) => fv.file_name(),
+        }
+    }
+
+    fn set_geo(&mut self, ngeo: Rect) {
+        match self {
+            View::Dir(ref mut dv) => dv.geo = ngeo,
+            View::File(ref mut fv) => fv.geo = ngeo,
+        }
+    }
+        
+    fn as_dir(&self) -> Option<&DirView> {
+        match self {
+            View::Dir(ref dv) => Some(dv),
+            _ => None,
+        }
+    }
+
+    fn as_file(&self) -> Option<&FileView> {
+        match self {
+            View::File(ref fv) => Some(fv),
+            _ => None,
+        }
+    }
+
+    fn as_dir_mut(&mut self) -> Option<&mut DirView> {
+        match self {
+            View::Dir(ref mut dv) => Some(dv),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    fn as_file_mut(&mut self) -> Option<&mut FileView> {
+        match self {
+            View::File(ref mut fv) => Some(fv),
+            _ => None,
+        }
+    }
+}
+
+impl From<DirView> for View {
+    fn from(dv: DirView) -> View {
+        View::Dir(dv)
+    }
+}
+
+impl From<FileView> for View {
+    fn from(fv: FileView) -> View {
+        View::File(fv)
+    }
+}
+
+impl<'a> Drawable<&ColorMap<'a>> for View { 
+    fn get_geo(&self) -> Rect {
+        match self {
+            View::Dir(dv) => dv.get_geo(),
+            View::File(fv) => fv.get_geo(),
+        }
+    }
+
+    fn draw(&mut self, d: &mut impl Canvas, c: &ColorMap) {
+        match self {
+            View::Dir(ref mut dv) => dv.draw(d, c),
+            View::File(ref mut fv) => fv.draw(d, c),
+        }
+    }
+}
+
+impl<'a> Nv<'a> {
+    #[allow(clippy::too_many_arguments)]
+    fn new( geo: Rect, 
+            dir: PathBuf, 
+            colors: ColorMap<'a>, 
+            binds: KeyBinds, 
+            column_info: ColumnInfo,
+            physical: bool,
+            auto_sizes: bool,
+            scroll_off: usize,
+            on_conflict: Option<ops::Policy>, ) -> Self { 
+
+        // A start dir that can't be resolved is shown with its error
+        let (dir, error) = match physical {
+            true  => match dir.canonicalize() {
+                Ok(dir) => (dir, None),
+                Err(e) => (logical_path(&dir), Some(io_error_string(&e))),
+            },
+            false => (logical_path(&dir), None),
+        };
+
+        let (event_sender, events) = mpsc::channel();
+
+        Self {
+            root: RootWin::new(geo.clone()),
+
+            views: {
+                let mut vm = ViewMap::new();
+                let dv = DirView::new(
+                    Rect::new(0, 0, geo.w, geo.h - 1),
+                    dir.clone(),
+                    scroll_off
+                );
+
+                vm.insert(dir.clone(), dv.into());
+                vm
+            },
+            cur_path: dir,
+
+            views_shown: 3,
+
+            status: StatusLine::new(Rect::new(0, geo.h - 1, geo.w, 1)),
+            prompt: None,
+            message: None,
+            error: error,
+
+            last_search: None,
+
+            finder: None,
+            finders_started: 0,
+
+            watcher: Watcher::new(event_sender.clone()),
+            unwatched: HashSet::new(),
+
+            git: HashMap::new(),
+            git_roots: HashMap::new(),
+            git_fetching: HashSet::new(),
+            git_stale: HashSet::new(),
+
+            events: events,
+            event_sender: event_sender,
+            keys_paused: Arc::new(AtomicBool::new(false)),
+
+            colors: colors,
+            binds: binds,
+
+            column_info: column_info,
+            show_info: true,
+            hide_ignored: false,
+
+            dir_sizes: HashMap::new(),
+            sizing: HashSet::new(),
+            size_jobs: vec![],
+            auto_sizes: auto_sizes,
+            sizes_cancelled: false,
+            usage: None,
+            clipboard: None,
+            paste: None,
+            trash: None,
+            on_conflict: on_conflict,
+
+            positions: state::Positions::load(),
+
+            physical: physical,
+            scroll_off: scroll_off,
+        }
+    }
+
+
+    fn start(&mut self) -> Result {
+        let mut orig_pos = self.root.abs_cursor_pos();
+        let adjusted     = self.root.ensure_geo();
+
+        orig_pos.1 -= adjusted.1;
+
+        {
+            let events = self.event_sender.clone();
+            let hide_ignored = self.hide_ignored;
+            let cv = self.views.get_mut(&self.cur_path).and_then(|v| v.as_dir_mut()).unwrap();
+
+            cv.scan_dir(events, hide_ignored);
+            cv.sort(SortOrder::Name);
+            match self.positions.get(&self.cur_path) {
+                Some((name, scroll)) => cv.restore_position(name, scroll),
+                None => cv.select_first(),
+            }
+
+            self.ensure_populated(1);
+            self.ensure_populated(-(self.views_shown as isize-2));
+        }
+
+        self.root.cursor().hide().unwrap();
+
+        // initial draw
+        self.update_watches();
+        self.update_git();
+        self.draw()?;
+
+        read_keys(self.event_sender.clone(), self.keys_paused.clone());
+
+        loop {
+            // The sender is kept alive by self so this can't fail
+            match self.events.recv().unwrap() {
+                Event::Key(c) => {
+                    if !self.handle_key(c) {
+                        break;
+                    }
+                },
+
+                Event::Found(id, paths) => {
+                    match self.finder {
+                        Some(ref mut f) if f.id() == id => f.add_candidates(paths),
+                        _ => continue,
+                    }
+                },
+
+                Event::FindDone(id) => {
+                    match self.finder {
+                        Some(ref mut f) if f.id() == id => f.set_done(),
+                        _ => continue,
+                    }
+                },
+
+                Event::DirChanged(dir) => self.dir_changed(&dir),
+
+                Event::Scanned(dir, id, entries) => {
+                    match self.views.get_mut(&dir).and_then(|v| v.as_dir_mut()) {
+                        Some(dv) => {
+                            dv.add_scanned(id, entries);
+                            dv.apply_dir_sizes(&self.dir_sizes);
+                        },
+                        None => continue,
+                    }
+                    self.ensure_populated(1);
+                },
+
+                Event::DirSize(dir, size, done) => {
+                    if done {
+                        self.sizing.remove(&dir);
+                        if self.sizing.is_empty() {
+                            self.size_jobs.clear();
+                        }
+
+                        // A recount changes the totals above by as much
+                        if let Some(old) = self.dir_sizes.insert(dir.clone(), size) {
+                            self.adjust_sizes_above(&dir, size as i64 - old as i64);
+                        }
+                    }
+
+                    let parent = dir.parent().and_then(|p| self.views.get_mut(p));
+                    if let Some(dv) = parent.and_then(|v| v.as_dir_mut()) {
+                        match done {
+                            true  => dv.set_dir_size(&dir, size),
+                            false => dv.set_partial_size(&dir, size),
+                        }
+                    }
+                },
+
+                Event::UsageProgress(root, scanned) => {
+                    match self.usage {
+                        Some(ref mut usage) if usage.root == root => usage.scanned = scanned,
+                        _ => (),
+                    }
+                },
+
+                Event::UsageDone(root, totals) => {
+                    match self.usage {
+                        Some(ref mut usage) if usage.root == root => usage.done = true,
+                        _ => continue,
+                    }
+                    self.dir_sizes.extend(totals);
+
+                    for (path, view) in self.views.iter_mut() {
+                        if let View::Dir(ref mut dv) = view {
+                            dv.apply_dir_sizes(&self.dir_sizes);
+                            if path.starts_with(&root) {
+                                dv.sort(SortOrder::Size);
+                            }
+                        }
+                    }
+                    self.ensure_populated(1);
+                    self.root.clear();
+                },
+
+                Event::Deleted(path, error) => self.deleted(&path, error, false),
+
+                Event::Trashed(path, error) => {
+                    self.deleted(&path, error, true);
+                    if let Some(files) = trash::files_dir() {
+                        self.dir_changed(&files);
+                    }
+                },
+
+                Event::Restored(path, res) => self.restored(&path, res),
+
+                Event::PasteProgress(progress) => {
+                    if let Some(ref mut paste) = self.paste {
+                        paste.progress = progress;
+                    }
+                },
+
+                Event::Pasted(src, dst, error) => self.pasted(src, dst, error),
+
+                Event::PasteSkipped => {
+                    if let Some(ref mut paste) = self.paste {
+                        paste.skipped += 1;
+                    }
+                },
+
+                Event::PasteConflict(dst) => {
+                    if let Some(ref mut paste) = self.paste {
+                        paste.conflict = Some(dst);
+                    }
+                },
+
+                Event::PasteDone => self.paste_done(),
+
+                Event::GitStatus(root, status) => {
+                    self.git_fetching.remove(&root);
+                    self.git.insert(root.clone(), status.map(Rc::new));
+                    if self.git_stale.remove(&root) {
+                        self.fetch_git_status(root);
+                    }
+                    self.root.clear();
+                },
+
+                Event::ScanDone(dir, id, error) => {
+                    match self.views.get_mut(&dir).and_then(|v| v.as_dir_mut()) {
+                        Some(dv) => {
+                            dv.finish_scan(id, error);
+                            dv.apply_dir_sizes(&self.dir_sizes);
+                        },
+                        None => continue,
+                    }
+                    self.ensure_populated(1);
+                    self.root.clear();
+                },
+            }
+
+            self.update_watches();
+            self.update_git();
+            self.ask_conflict();
+            if self.auto_sizes && !self.sizes_cancelled {
+                self.compute_sizes();
+            }
+            self.draw()?;
+        }
+
+        self.root.cursor().show().unwrap();
+
+        self.save_positions();
+
+        self.end(orig_pos)
+    }
+
+    // Remembers where we were in every dir for the next session
+    fn save_positions(&mut self) {
+        // The cursor of a dir still being scanned isn't where it was left
+        let mut left: Vec<(&PathBuf, &OsStr, usize)> = self.views.iter()
+            .filter_map(|(dir, view)| view.as_dir().filter(|dv| !dv.is_loading()).map(|dv| (dir, dv)))
+            .filter_map(|(dir, dv)| Some((dir, dv.sel_file_name()?, dv.scroll)))
+            .collect();
+
+        // All left at once, they go first in the order of their paths so
+        // the file doesn't change with the order of the map. Each is set
+        // in front of the ones before it
+        left.sort_by(|a, b| b.0.cmp(a.0));
+        for (dir, name, scroll) in left {
+            self.positions.set(dir.clone(), name.to_owned(), scroll);
+        }
+
+        // Not worth bothering anyone about on the way out
+        let _ = self.positions.save();
+    }
+
+    // Returns false when nv should quit
+    fn handle_key(&mut self, c: char) -> bool {
+        self.message = None;
+        self.error = None;
+
+        if self.prompt.is_some() {
+            self.prompt_input(c);
+            return true;
+        }
+
+        let action = match self.binds.get(&c) {
+            Some(action) => *action,
+            None => return true,
+        };
+
+        match action {
+            Action::Quit => return false,
+
+            Action::MoveDown(..) |
+            Action::MoveUp(..) => {
+                let n = match action {
+                    Action::MoveDown(nn) => nn as isize,
+                    Action::MoveUp(nn)   => -(nn as isize),
+                    _ => unreachable!(),
+                };
+
+                let cv = match self.get_dir_mut(0) {
+                    Some(cv) => cv,
+                    None => return true,
+                };
+
+                if cv.inc_sel(n as isize) != 0 {
+
+                    cv.ensure_sel_in_view();
+                    self.ensure_populated(1);
+
+                    self.root.clear();
+                }
+            },
+
+            Action::PageDown |
+            Action::PageUp |
+            Action::HalfPageDown |
+            Action::HalfPageUp => {
+                let cv = match self.get_dir_mut(0) {
+                    Some(cv) => cv,
+                    None => return true,
+                };
+
+                let page = cv.geo.h as isize;
+                let half = std::cmp::max(page / 2, 1);
+
+                let n = match action {
+                    Action::PageDown     => page,
+                    Action::PageUp       => -page,
+                    Action::HalfPageDown => half,
+                    Action::HalfPageUp   => -half,
+                    _ => unreachable!(),
+                };
+
+                if cv.scroll_sel(n) != 0 {
+                    self.ensure_populated(1);
+                }
+                self.root.clear();
+            },
+
+            Action::GoFirst |
+            Action::GoLast |
+            Action::GoMiddle => {
+                let cv = match self.get_dir_mut(0) {
+                    Some(cv) => cv,
+                    None => return true,
+                };
+
+                let index = match action {
+                    Action::GoFirst  => 0,
+                    Action::GoLast   => cv.sorted_indices.len().saturating_sub(1),
+                    Action::GoMiddle => cv.middle_index(),
+                    _ => unreachable!(),
+                };
+
+                if cv.set_sel(index) != 0 {
+
+                    cv.ensure_sel_in_view();
+                    self.ensure_populated(1);
+
+                    self.root.clear();
+                }
+            },
+
+            Action::MoveLeft(..) |
+            Action::MoveRight(..) => {
+                let n = match action {
+                    Action::MoveLeft(nn) => -(nn as isize),
+                    Action::MoveRight(nn)  => nn as isize,
+                    _ => unreachable!(),
+                };
+
+                let steps = self.ensure_populated(n);
+                let path = match self.get_view(steps) {
+                    Some(view) => view.path().to_owned(),
+                    None => return true,
+                };
+
+                if steps != 0 {
+                    self.cur_path = path;
+
+                    if self.physical && steps > 0 {
+                        self.resolve_cur_path();
+                    }
+
+                    self.root.clear();
+                }
+            },
+
+            Action::FollowLink => self.follow_link(),
+
+            Action::ToggleIgnored => {
+                self.hide_ignored = !self.hide_ignored;
+                self.message = Some(String::from(match self.hide_ignored {
+                    true  => "hiding ignored files",
+                    false => "showing ignored files",
+                }));
+
+                // Every cached listing is out of date now
+                let events = self.event_sender.clone();
+                for view in self.views.values_mut() {
+                    if let View::Dir(ref mut dv) = view {
+                        dv.scan_dir(events.clone(), self.hide_ignored);
+                    }
+                }
+            },
+
+            Action::Filter => self.start_filter_prompt(),
+
+            Action::ClearFilter => self.set_cur_filter(None),
+
+            Action::Search => self.start_search_prompt(),
+
+            Action::SearchNext => self.search_next(true),
+            Action::SearchPrev => self.search_next(false),
+
+            Action::Find => self.start_find_prompt(),
+
+            Action::ToggleInfo => {
+                self.show_info = !self.show_info;
+                self.root.clear();
+            },
+
+            Action::CancelScan => {
+                if let Some(cv) = self.get_dir_mut(0) {
+                    if cv.is_loading() {
+                        cv.cancel_scan();
+                        self.message = Some(String::from("scan cancelled"));
+                        self.root.clear();
+                    }
+                }
+
+                if !self.sizing.is_empty() {
+                    self.cancel_sizes();
+                    self.sizes_cancelled = true;
+                    self.message = Some(String::from("size calculation cancelled"));
+                }
+
+                if self.usage.as_ref().is_some_and(|usage| !usage.done) {
+                    self.toggle_usage();
+                }
+
+                if let Some(ref paste) = self.paste {
+                    paste.cancel.store(true, Ordering::Relaxed);
+                    let _ = paste.answers.send(None);
+                }
+            },
+
+            Action::ComputeSizes => self.compute_sizes(),
+
+            Action::DiskUsage => self.toggle_usage(),
+
+            Action::Delete => self.start_delete_prompt(),
+
+            Action::Trash => {
+                let paths = match self.get_dir_mut(0) {
+                    Some(cv) => {
+                        let paths = cv.marked_paths();
+                        cv.clear_marks();
+                        paths
+                    },
+                    None => return true,
+                };
+
+                trash::trash(paths, self.event_sender.clone());
+                self.root.clear();
+            },
+
+            Action::Rename => self.start_rename_prompt(),
+            Action::BulkRename => self.edit_names(),
+            Action::BatchRename => self.start_batch_rename_prompt(),
+            Action::NewFile => self.start_create_prompt(ops::NewEntry::File),
+            Action::NewDir => self.start_create_prompt(ops::NewEntry::Dir),
+            Action::NewSymlink | Action::NewHardlink => {
+                let target = match self.get_dir(0).and_then(|cv| cv.sel_path()) {
+                    Some(path) => path.to_owned(),
+                    None => return true,
+                };
+                self.start_create_prompt(match action {
+                    Action::NewSymlink => ops::NewEntry::Symlink(target),
+                    _ => ops::NewEntry::Hardlink(target),
+                });
+            },
+
+            Action::GotoTrash => self.goto_trash(),
+
+            Action::Restore => {
+                let in_trash = self.get_dir(0)
+                    .is_some_and(|cv| Some(&cv.dir) == trash::files_dir().as_ref());
+                if !in_trash {
+                    self.error = Some(String::from("only trashed entries can be restored"));
+                    return true;
+                }
+
+                let cv = match self.get_dir_mut(0) {
+                    Some(cv) => cv,
+                    None => return true,
+                };
+                let paths = cv.marked_paths();
+                cv.clear_marks();
+
+                trash::restore(paths, self.event_sender.clone());
+                self.root.clear();
+            },
+
+            Action::ToggleMark => {
+                if let Some(cv) = self.get_dir_mut(0) {
+                    cv.toggle_mark();
+                    cv.inc_sel(1);
+                    cv.ensure_sel_in_view();
+
+                    self.ensure_populated(1);
+                    self.root.clear();
+                }
+            },
+
+            Action::Yank => self.yank(false),
+
+            Action::Cut => self.yank(true),
+
+            Action::Paste => self.paste(),
+
+            Action::CycleSort => {
+                if let Some(cv) = self.get_dir_mut(0) {
+                    let order = match cv.sort_order {
+                        SortOrder::Name => SortOrder::Size,
+                        SortOrder::Size => SortOrder::Name,
+                    };
+                    cv.sort(order);
+
+                    self.message = Some(format!("sorted by {}", match order {
+                        SortOrder::Name => "name",
+                        SortOrder::Size => "size",
+                    }));
+                    self.ensure_populated(1);
+                    self.root.clear();
+                }
+            },
+        }
+
+        return true;
+    }
+
+    fn draw(&mut self) -> Result {
+        let shown = self.views_shown;
+        let pre = (shown as isize)-2;
+
+        let width = ((self.root.geo.w as f32 -1.0) / shown as f32).floor() as u16;
+        // the last line is reserved for the status line
+        let height = self.root.geo.h - 1;
+        
+        let a_ofs = -pre;
+        let b_ofs = 1;
+
+        // The finder takes over the columns while it's open
+        if let Some(ref mut finder) = self.finder {
+            finder.geo = Rect::new(0, 0, self.root.geo.w, height);
+            self.root.draw(finder, &self.colors);
+
+            self.update_status();
+            self.status.geo = Rect::new(0, height, self.root.geo.w, 1);
+            self.root.draw(&mut self.status, &self.colors);
+
+            return Ok(());
+        }
+
+        // The trashed entries show where they came from
+        let trash_files = trash::files_dir();
+        let trash_shown = trash_files.as_ref().is_some_and(|dir| self.shown_paths().contains(dir));
+        if trash_shown && self.trash.is_none() {
+            self.trash = Some(Rc::new(trash::list()));
+        }
+
+        let (git, git_roots) = (&self.git, &self.git_roots);
+        let usage_root = self.usage.as_ref()
+            .filter(|usage| usage.done)
+            .map(|usage| usage.root.as_path());
+
+        for ofs in a_ofs..(b_ofs+1) {
+            if let Some(path) = self.traverse_dirs(ofs) {
+                if let Some(view) = self.views.get_mut(&path) {
+                    view.set_geo(Rect {
+                        x: (ofs+pre) as u16 * width, 
+                        y: 0,
+                        w: width,
+                        h: height,
+                    });
+                    if let View::Dir(ref mut dv) = view {
+                        dv.info = match self.show_info {
+                            true  => self.column_info.get(&ofs).cloned().unwrap_or_default(),
+                            false => vec![],
+                        };
+                        dv.git = git_roots.get(&path)
+                            .and_then(|root| git.get(root.as_ref()?)?.clone());
+                        dv.usage = usage_root.is_some_and(|root| path.starts_with(root));
+                        dv.trash = self.trash.clone().filter(|_| Some(&path) == trash_files.as_ref());
+                        dv.editing = match self.prompt {
+                            Some(Prompt { kind: PromptKind::Rename { ref editor, .. }, .. }) if ofs == 0 => {
+                                Some(editor.clone())
+                            },
+                            _ => None,
+                        };
+                        dv.renames = match self.prompt {
+                            Some(Prompt { kind: PromptKind::Confirm(Confirmation::Rename(ref dir, ref renames)), .. }) |
+                            Some(Prompt { kind: PromptKind::BatchRename { ref dir, ref renames, .. }, .. }) if *dir == path => {
+                                Some(renames.clone())
+                            },
+                            _ => None,
+                        };
+                    }
+                    self.root.draw(view, &self.colors);
+                }
+            }
+        }
+
+        self.update_status();
+        self.status.geo = Rect::new(0, height, self.root.geo.w, 1);
+        self.root.draw(&mut self.status, &self.colors);
+
+        Ok(())
+    }
+
+    fn update_status(&mut self) {
+        self.status.cursor = None;
+        self.status.error = None;
+
+        if let Some(ref prompt) = self.prompt {
+            match prompt.kind {
+                PromptKind::Filter { kind, .. } => {
+                    self.status.text = format!("filter ({}): {}", kind.name(), prompt.text);
+                },
+                PromptKind::Search { .. } => {
+                    self.status.text = format!("/{}", prompt.text);
+                },
+                PromptKind::Find => {
+                    self.status.text = format!("find: {}", prompt.text);
+                },
+                PromptKind::Confirm(..) => {
+                    self.status.text = format!("{} (y/n)", prompt.text);
+                },
+                PromptKind::Conflict => {
+                    self.status.text = prompt.text.clone();
+                },
+                PromptKind::Rename { .. } => {
+                    self.status.text = String::from("rename: enter to confirm, esc to cancel");
+                },
+                PromptKind::BatchRename { .. } => {
+                    self.status.text = format!("rename (s/regex/new/ or template): {}", prompt.text);
+                },
+                PromptKind::Create { ref new, .. } => {
+                    self.status.text = match new {
+                        ops::NewEntry::Symlink(target) | ops::NewEntry::Hardlink(target) => format!(
+                            "new {} to {}: ",
+                            new.name(), display_name(target.file_name().unwrap_or_default())
+                        ),
+                        _ => format!("new {}: ", new.name()),
+                    };
+                },
+            }
+            // The name being edited has its own cursor
+            self.status.cursor = match prompt.kind {
+                PromptKind::Rename { .. } => None,
+                PromptKind::Create { ref editor, .. } => {
+                    let cursor = self.status.text.chars().count() + editor.cursor();
+                    self.status.text.push_str(&editor.text());
+                    Some(cursor)
+                },
+                _ => Some(self.status.text.chars().count()),
+            };
+            self.status.error = prompt.error.clone();
+
+            if let Some(ref finder) = self.finder {
+                self.status.text.push_str(&format!("  {}", finder.status()));
+            }
+            return;
+        }
+
+        self.status.text = match self.get_dir(0) {
+            Some(cv) => match cv.filter {
+                Some(ref filter) => format!(
+                    "filter ({}): {}  [{}/{}]",
+                    filter.kind.name(), filter.pattern,
+                    cv.sorted_indices.len(), cv.entry_count()
+                ),
+                None => String::new(),
+            },
+            None => String::new(),
+        };
+
+        let loading = self.get_dir(0)
+            .filter(|cv| cv.is_loading())
+            .map(|cv| cv.entry_count());
+        if let Some(count) = loading {
+            if !self.status.text.is_empty() {
+                self.status.text.push_str("  ");
+            }
+            self.status.text.push_str(&format!("[loading… {}]", count));
+        }
+
+        let marks = self.get_dir(0).map_or(0, |cv| cv.mark_count());
+        if marks > 0 {
+            if !self.status.text.is_empty() {
+                self.status.text.push_str("  ");
+            }
+            self.status.text.push_str(&format!("[{} marked]", marks));
+        }
+
+        if !self.sizing.is_empty() {
+            if !self.status.text.is_empty() {
+                self.status.text.push_str("  ");
+            }
+            self.status.text.push_str(&format!("[sizing… {} left]", self.sizing.len()));
+        }
+
+        if let Some(ref paste) = self.paste {
+            if !self.status.text.is_empty() {
+                self.status.text.push_str("  ");
+            }
+
+            let p = &paste.progress;
+            let file_pct = match p.file_size {
+                0 => 100,
+                _ => p.file_done * 100 / p.file_size,
+            };
+            self.status.text.push_str(&format!(
+                "[{} {} {}%  {}/{}]",
+                match paste.cut {
+                    true  => "moving",
+                    false => "copying",
+                },
+                display_name(&p.name), file_pct,
+                info::human_size(p.done), info::human_size(p.total),
+            ));
+        }
+
+        if let Some(ref usage) = self.usage {
+            if !self.status.text.is_empty() {
+                self.status.text.push_str("  ");
+            }
+            self.status.text.push_str(&match usage.done {
+                true  => format!("[disk usage of {}]", display_name(usage.root.as_os_str())),
+                false => format!("[disk usage… {}]", info::human_size(usage.scanned)),
+            });
+        }
+
+        if let Some(ref msg) = self.message {
+            if !self.status.text.is_empty() {
+                self.status.text.push_str("  ");
+            }
+            self.status.text.push_str(msg);
+        }
+        self.status.error = self.error.clone();
+    }
+
+    fn start_filter_prompt(&mut self) {
+        let orig = match self.get_dir(0) {
+            Some(cv) => cv.filter.clone(),
+            None => return,
+        };
+
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Filter {
+                kind: orig.as_ref().map_or(FilterKind::Substring, |f| f.kind),
+                orig: orig.clone(),
+            },
+            text: orig.map_or(String::new(), |f| f.pattern),
+            error: None,
+        });
+    }
+
+    fn prompt_input(&mut self, c: char) {
+        let mut prompt = match self.prompt.take() {
+            Some(prompt) => prompt,
+            None => return,
+        };
+
+        if let PromptKind::Confirm(..) = prompt.kind {
+            return match c {
+                'y' | 'Y' => self.confirm_prompt(prompt),
+                _ => self.cancel_prompt(prompt),
+            };
+        }
+
+        if let PromptKind::Rename { ref path, ref mut editor } = prompt.kind {
+            match c {
+                '\r' | '\n' => return self.confirm_prompt(prompt),
+                '\x1b' | '\x03' => return self.cancel_prompt(prompt),
+                c => {
+                    if editor.input(c) {
+                        prompt.error = validate_name(path, &editor.text()).err();
+                    }
+                },
+            }
+            self.prompt = Some(prompt);
+            return;
+        }
+
+        if let PromptKind::Create { ref dir, ref new, ref mut editor } = prompt.kind {
+            match c {
+                '\r' | '\n' => return self.confirm_prompt(prompt),
+                '\x1b' | '\x03' => return self.cancel_prompt(prompt),
+                c => {
+                    if editor.input(c) {
+                        prompt.error = validate_new_name(dir, &editor.text(), new).err();
+                    }
+                },
+            }
+            self.prompt = Some(prompt);
+            return;
+        }
+
+        if let PromptKind::Conflict = prompt.kind {
+            let policy = match c.to_ascii_lowercase() {
+                'o' => ops::Policy::Overwrite,
+                's' => ops::Policy::Skip,
+                'r' => ops::Policy::Rename,
+                'n' => ops::Policy::OverwriteIfNewer,
+                '\x1b' | '\x03' => return self.cancel_prompt(prompt),
+                _ => {
+                    self.prompt = Some(prompt);
+                    return;
+                },
+            };
+
+            if let Some(ref paste) = self.paste {
+                let _ = paste.answers.send(Some(ops::Answer {
+                    policy: policy,
+                    all: c.is_ascii_uppercase(),
+                }));
+            }
+            return;
+        }
+
+        let changed = match c {
+            '\r' | '\n' => return self.confirm_prompt(prompt),
+            '\x1b' | '\x03' => return self.cancel_prompt(prompt),
+
+            '\x7f' | '\x08' => prompt.text.pop().is_some(),
+
+            '\t' => match prompt.kind {
+                PromptKind::Filter { ref mut kind, .. } => {
+                    *kind = kind.next();
+                    true
+                },
+                _ => false,
+            },
+
+            // ctrl-n and ctrl-p or the arrows move through the finder's
+            // matches
+            '\x0e' | '\x10' | keys::DOWN | keys::UP => {
+                if let Some(ref mut finder) = self.finder {
+                    finder.inc_sel(if c == '\x0e' || c == keys::DOWN { 1 } else { -1 });
+                }
+                false
+            },
+
+            c if !c.is_control() && !keys::is_special(c) => {
+                prompt.text.push(c);
+                true
+            },
+
+            _ => false,
+        };
+
+        if changed {
+            self.update_prompt(&mut prompt);
+        }
+        self.prompt = Some(prompt);
+    }
+
+    // Applies the prompt's current text live
+    fn update_prompt(&mut self, prompt: &mut Prompt) {
+        match prompt.kind {
+            PromptKind::Filter { kind, .. } => {
+                let filter = match prompt.text.as_str() {
+                    "" => Ok(None),
+                    text => Filter::new(kind, text).map(Some),
+                };
+
+                match filter {
+                    Ok(filter) => {
+                        prompt.error = None;
+                        self.set_cur_filter(filter);
+                    },
+                    // keep the last valid filter while the pattern is incomplete
+                    Err(e) => prompt.error = Some(e),
+                }
+            },
+
+            PromptKind::Search { orig_sel, .. } => {
+                let search = match prompt.text.as_str() {
+                    "" => None,
+                    text => Some(Search::new(text)),
+                };
+
+                prompt.error = None;
+
+                if let Some(cv) = self.get_dir_mut(0) {
+                    cv.sel[0] = orig_sel;
+
+                    if let Some(ref search) = search {
+                        if !cv.select_match(search, orig_sel, true) {
+                            prompt.error = Some("no match".to_owned());
+                        }
+                    }
+
+                    cv.ensure_sel_in_view();
+                    cv.search = search;
+                }
+
+                self.ensure_populated(1);
+                self.root.clear();
+            },
+
+            PromptKind::Find => {
+                if let Some(ref mut finder) = self.finder {
+                    finder.set_pattern(&prompt.text);
+                }
+                self.root.clear();
+            },
+
+            PromptKind::Confirm(..) => (),
+            PromptKind::Conflict => (),
+            PromptKind::Rename { .. } => (),
+            PromptKind::Create { .. } => (),
+
+            PromptKind::BatchRename { ref dir, ref old, ref names, ref mut renames } => {
+                match rename::substitute(dir, names, &prompt.text) {
+                    Ok(new) => {
+                        *renames = old.iter().zip(new.iter())
+                            .filter(|(old, new)| old.as_os_str() != OsStr::new(new))
+                            .map(|(old, new)| (old.clone(), OsString::from(new)))
+                            .collect();
+                        prompt.error = rename::plan(dir, old, &new).err();
+                    },
+                    // keep the last preview while the pattern is incomplete
+                    Err(e) => prompt.error = Some(e),
+                }
+                self.root.clear();
+            },
+        }
+    }
+
+    fn confirm_prompt(&mut self, prompt: Prompt) {
+        match prompt.kind {
+            PromptKind::Filter { orig, .. } => {
+                if prompt.error.is_some() {
+                    self.set_cur_filter(orig);
+                }
+            },
+
+            PromptKind::Search { orig, .. } => {
+                if prompt.text.is_empty() {
+                    self.set_cur_search(None, orig);
+                } else {
+                    self.last_search = Some(Search::new(&prompt.text));
+                    self.error = prompt.error;
+                }
+            },
+
+            PromptKind::Find => {
+                let path = self.finder.take().and_then(|f| f.sel_path());
+
+                if let Some(path) = path {
+                    self.goto_entry(&path);
+                }
+                self.root.clear();
+            },
+
+            PromptKind::Confirm(Confirmation::Delete(paths)) => ops::delete(paths, self.event_sender.clone()),
+
+            PromptKind::Confirm(Confirmation::Rename(dir, renames)) => self.rename_all(&dir, &renames),
+
+            PromptKind::Conflict => (),
+
+            PromptKind::BatchRename { dir, old, names, renames } => {
+                let planned = rename::substitute(&dir, &names, &prompt.text)
+                    .and_then(|new| rename::plan(&dir, &old, &new));
+
+                match planned {
+                    Ok(ref planned) if planned.is_empty() => {
+                        self.message = Some(String::from("no names changed"));
+                    },
+                    Ok(planned) => self.rename_all(&dir, &planned),
+                    // Kept open to fix the pattern, nothing's renamed while
+                    // any names collide
+                    Err(error) => {
+                        self.prompt = Some(Prompt {
+                            kind: PromptKind::BatchRename { dir: dir, old: old, names: names, renames: renames },
+                            text: prompt.text,
+                            error: Some(error),
+                        });
+                    },
+                }
+                self.root.clear();
+            },
+
+            PromptKind::Create { dir, new, editor } => {
+                // Kept open to fix the name if it can't be made
+                if let Err(error) = self.create(&dir, &new, &editor.text()) {
+                    self.prompt = Some(Prompt {
+                        kind: PromptKind::Create { dir: dir, new: new, editor: editor },
+                        text: prompt.text,
+                        error: Some(error),
+                    });
+                }
+            },
+
+            PromptKind::Rename { path, editor } => {
+                // Kept open to fix the name if it can't be renamed
+                if let Err(error) = self.rename(&path, &editor.text()) {
+                    self.prompt = Some(Prompt {
+                        kind: PromptKind::Rename { path: path, editor: editor },
+                        text: prompt.text,
+                        error: Some(error),
+                    });
+                }
+            },
+        }
+    }
+
+    fn cancel_prompt(&mut self, prompt: Prompt) {
+        match prompt.kind {
+            PromptKind::Filter { orig, .. } => self.set_cur_filter(orig),
+            PromptKind::Search { orig_sel, orig } => self.set_cur_search(Some(orig_sel), orig),
+            PromptKind::Find => {
+                self.finder = None;
+                self.root.clear();
+            },
+            PromptKind::Confirm(..) => (),
+            PromptKind::Rename { .. } => self.root.clear(),
+            PromptKind::BatchRename { .. } => self.root.clear(),
+            PromptKind::Create { .. } => (),
+            // Not answering cancels the whole paste
+            PromptKind::Conflict => {
+                if let Some(ref paste) = self.paste {
+                    paste.cancel.store(true, Ordering::Relaxed);
+                    let _ = paste.answers.send(None);
+                }
+            },
+        }
+    }
+
+    fn start_find_prompt(&mut self) {
+        self.finders_started += 1;
+
+        self.finder = Some(Finder::new(
+            Rect::new(0, 0, self.root.geo.w, self.root.geo.h - 1),
+            self.finders_started,
+            self.cur_path.clone(),
+            self.event_sender.clone(),
+        ));
+
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Find,
+            text: String::new(),
+            error: None,
+        });
+
+        self.root.clear();
+    }
+
+    // Paths of the views shown in the columns, left to right
+    fn shown_paths(&self) -> Vec<PathBuf> {
+        let pre = self.views_shown as isize - 2;
+
+        (-pre..2).filter_map(|ofs| self.traverse_dirs(ofs)).collect()
+    }
+
+    // Watches the shown dirs, rescanning the ones that might have changed
+    // while they weren't watched
+    fn update_watches(&mut self) {
+        let dirs: Vec<PathBuf> = self.shown_paths().into_iter()
+            .filter(|p| self.views.get(p).and_then(|v| v.as_dir()).is_some())
+            .collect();
+
+        let added = match self.watcher {
+            Some(ref mut watcher) => watcher.watch_only(&dirs),
+            None => return,
+        };
+
+        for dir in added {
+            if self.unwatched.remove(&dir) {
+                if let Some(dv) = self.views.get_mut(&dir).and_then(|v| v.as_dir_mut()) {
+                    dv.scan_dir(self.event_sender.clone(), self.hide_ignored);
+                }
+            }
+        }
+
+        // Every other cached dir is now unwatched
+        self.unwatched = self.views.iter()
+            .filter(|(p, v)| v.as_dir().is_some() && !dirs.contains(p))
+            .map(|(p, _)| p.clone())
+            .collect();
+    }
+
+    // Fetches the status of the repositories of the shown dirs that
+    // weren't fetched yet
+    fn update_git(&mut self) {
+        let dirs: Vec<PathBuf> = self.shown_paths().into_iter()
+            .filter(|p| self.views.get(p).and_then(|v| v.as_dir()).is_some())
+            .collect();
+
+        for dir in dirs {
+            let root = self.git_roots.entry(dir.clone())
+                .or_insert_with(|| git::repo_root(&dir))
+                .clone();
+
+            if let Some(root) = root {
+                if !self.git.contains_key(&root) && !self.git_fetching.contains(&root) {
+                    self.fetch_git_status(root);
+                }
+            }
+        }
+    }
+
+    fn fetch_git_status(&mut self, root: PathBuf) {
+        if self.git_fetching.contains(&root) {
+            self.git_stale.insert(root);
+            return;
+        }
+
+        self.git_fetching.insert(root.clone());
+        git::fetch_status(root, self.event_sender.clone());
+    }
+
+    // Computes the sizes of the dirs in the current column that aren't
+    // known or being computed yet
+    fn compute_sizes(&mut self) {
+        let dirs: Vec<PathBuf> = match self.get_dir(0) {
+            Some(cv) => cv.unsized_dirs().into_iter()
+                .filter(|d| !self.sizing.contains(d))
+                .collect(),
+            None => return,
+        };
+
+        if dirs.is_empty() {
+            return;
+        }
+
+        self.sizing.extend(dirs.iter().cloned());
+        self.size_jobs.push(size::spawn(dirs, self.event_sender.clone()));
+    }
+
+    // Starts or ends the disk usage mode for the subtree of the current dir
+    fn toggle_usage(&mut self) {
+        if let Some(usage) = self.usage.take() {
+            usage.cancel.store(true, Ordering::Relaxed);
+
+            for view in self.views.values_mut() {
+                if let View::Dir(ref mut dv) = view {
+                    dv.sort(SortOrder::Name);
+                }
+            }
+            self.message = Some(String::from("disk usage mode off"));
+            self.ensure_populated(1);
+            self.root.clear();
+            return;
+        }
+
+        self.usage = Some(Usage {
+            root: self.cur_path.clone(),
+            scanned: 0,
+            done: false,
+            cancel: size::spawn_tree(self.cur_path.clone(), self.event_sender.clone()),
+        });
+    }
+
+    // New views are sorted by size in the disk usage mode
+    fn sort_order_for(&self, dir: &Path) -> SortOrder {
+        match self.usage {
+            Some(ref usage) if usage.done && dir.starts_with(&usage.root) => SortOrder::Size,
+            _ => SortOrder::Name,
+        }
+    }
+
+    fn start_delete_prompt(&mut self) {
+        let paths = match self.get_dir(0) {
+            Some(cv) => cv.marked_paths(),
+            None => return,
+        };
+
+        let what = match paths.len() {
+            0 => return,
+            1 => display_name(paths[0].file_name().unwrap_or_default()),
+            n => format!("{} entries", n),
+        };
+
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Confirm(Confirmation::Delete(paths)),
+            text: format!("delete {} permanently?", what),
+            error: None,
+        });
+    }
+
+    fn start_rename_prompt(&mut self) {
+        let path = match self.get_dir(0).and_then(|cv| cv.sel_path()) {
+            Some(path) => path.to_owned(),
+            None => return,
+        };
+
+        let name = match path.file_name().unwrap_or_default().to_str() {
+            Some(name) => name.to_owned(),
+            None => {
+                self.error = Some(String::from("only UTF-8 names can be edited"));
+                return;
+            },
+        };
+
+        // The cursor starts before the extension of files
+        let cursor = match path.is_dir() {
+            true  => name.len(),
+            false => name.rfind('.').filter(|&i| i > 0).unwrap_or(name.len()),
+        };
+
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Rename {
+                editor: LineEditor::new(&name, name[..cursor].chars().count()),
+                path: path,
+            },
+            text: String::new(),
+            error: None,
+        });
+    }
+
+    // Renames the entry at `path` to `name` in the same dir, keeping it
+    // selected
+    fn rename(&mut self, path: &Path, name: &str) -> std::result::Result<(), String> {
+        let (dir, old) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(old)) => (dir.to_owned(), old.to_owned()),
+            _ => return Err(String::from("can't rename that")),
+        };
+
+        if old == name {
+            self.root.clear();
+            return Ok(());
+        }
+
+        validate_name(path, name)?;
+        fs::rename(path, dir.join(name)).map_err(|e| io_error_string(&e))?;
+
+        if let Some(dv) = self.views.get_mut(&dir).and_then(|v| v.as_dir_mut()) {
+            dv.rename_entries(&[(old.clone(), OsString::from(name))]);
+        }
+
+        // Whatever was under the old path is under the new one now
+        self.views.retain(|p, _| !p.starts_with(path));
+        self.dir_changed(&dir);
+
+        self.message = Some(format!("renamed {} to {}", display_name(&old), display_name(OsStr::new(name))));
+        Ok(())
+    }
+
+    // Links start out with the name of their target
+    fn start_create_prompt(&mut self, new: ops::NewEntry) {
+        let dir = match self.get_dir(0) {
+            Some(cv) => cv.dir.clone(),
+            None => return,
+        };
+
+        let name = match new {
+            ops::NewEntry::Symlink(ref target) | ops::NewEntry::Hardlink(ref target) => {
+                target.file_name().and_then(|n| n.to_str()).unwrap_or("").to_owned()
+            },
+            _ => String::new(),
+        };
+
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Create {
+                editor: LineEditor::new(&name, name.chars().count()),
+                dir: dir,
+                new: new,
+            },
+            text: String::new(),
+            error: None,
+        });
+    }
+
+    // Makes the `new` entry `name` in `dir` and selects it, or the topmost of
+    // the new parent dirs
+    fn create(&mut self, dir: &Path, new: &ops::NewEntry, name: &str) -> std::result::Result<(), String> {
+        validate_new_name(dir, name, new)?;
+        ops::create(&dir.join(name), new).map_err(|e| io_error_string(&e))?;
+
+        self.dir_changed(dir);
+        if let Some(first) = Path::new(name).components().next() {
+            self.goto_entry(&dir.join(first));
+        }
+
+        self.message = Some(format!("created {} {}", new.name(), name.trim_end_matches('/')));
+        Ok(())
+    }
+
+    // The current dir and the names of its marked entries, also as text to
+    // be edited. None if there are none or some can't be edited
+    fn marked_names(&mut self) -> Option<(PathBuf, Vec<OsString>, Vec<String>)> {
+        let cv = self.get_dir(0)?;
+        let dir = cv.dir.clone();
+        let old: Vec<OsString> = cv.marked_paths().iter()
+            .filter_map(|p| p.file_name())
+            .map(|n| n.to_owned())
+            .collect();
+        if old.is_empty() {
+            return None;
+        }
+
+        // Edited one per line
+        let names: Option<Vec<String>> = old.iter()
+            .map(|n| n.to_str().filter(|n| !n.contains('\n')).map(String::from))
+            .collect();
+        if names.is_none() {
+            self.error = Some(String::from("only UTF-8 names without newlines can be edited"));
+        }
+
+        Some((dir, old, names?))
+    }
+
+    // Lets the names of the marked entries be edited all at once in an
+    // editor, the renames are asked about before they're done
+    fn edit_names(&mut self) {
+        let (dir, old, names) = match self.marked_names() {
+            Some(marked) => marked,
+            None => return,
+        };
+
+        let edited = self.suspended(|| rename::edit_names(&names));
+        let renames = edited
+            .map_err(|e| io_error_string(&e))
+            .and_then(|edited| rename::plan(&dir, &old, &edited));
+
+        let renames = match renames {
+            Ok(renames) => renames,
+            Err(error) => {
+                self.error = Some(error);
+                return;
+            },
+        };
+
+        let what = match renames.len() {
+            0 => {
+                self.message = Some(String::from("no names changed"));
+                return;
+            },
+            1 => format!("{} to {}", display_name(&renames[0].0), display_name(&renames[0].1)),
+            n => format!("{} entries", n),
+        };
+
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Confirm(Confirmation::Rename(dir, renames)),
+            text: format!("rename {}?", what),
+            error: None,
+        });
+    }
+
+    fn start_batch_rename_prompt(&mut self) {
+        let (dir, old, names) = match self.marked_names() {
+            Some(marked) => marked,
+            None => return,
+        };
+
+        self.prompt = Some(Prompt {
+            kind: PromptKind::BatchRename { dir: dir, old: old, names: names, renames: vec![] },
+            text: String::new(),
+            error: None,
+        });
+    }
+
+    // Renames the entries of `dir` as planned, keeping the selections on them
+    fn rename_all(&mut self, dir: &Path, renames: &rename::Renames) {
+        // Nothing's left half done if it fails, but the dir may have changed
+        if let Err(error) = rename::apply(dir, renames) {
+            self.error = Some(error);
+            self.dir_changed(dir);
+            return;
+        }
+
+        if let Some(dv) = self.views.get_mut(dir).and_then(|v| v.as_dir_mut()) {
+            dv.rename_entries(renames);
+        }
+
+        for (old, _) in renames.iter() {
+            let path = dir.join(old);
+            self.views.retain(|p, _| !p.starts_with(&path));
+        }
+        self.dir_changed(dir);
+
+        self.message = Some(match renames.len() {
+            1 => String::from("renamed 1 entry"),
+            n => format!("renamed {} entries", n),
+        });
+    }
+
+    // Runs `f` while another program has the terminal, redrawing everything
+    // once it's back
+    fn suspended<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        self.keys_paused.store(true, Ordering::SeqCst);
+        self.root.suspend();
+
+        let res = f();
+
+        self.root.resume();
+        self.keys_paused.store(false, Ordering::SeqCst);
+        self.root.clear();
+        res
+    }
+
+    // Shows the entries in the trash, once there is one
+    fn goto_trash(&mut self) {
+        let files = match trash::files_dir() {
+            Some(files) if files.is_dir() => files,
+            _ => {
+                self.message = Some(String::from("the trash is empty"));
+                return;
+            },
+        };
+
+        if self.ensure_dir_view(&files).is_none() {
+            return;
+        }
+
+        self.cur_path = files;
+        self.ensure_populated(1);
+        self.ensure_populated(-(self.views_shown as isize-2));
+        self.root.clear();
+    }
+
+    fn restored(&mut self, path: &Path, res: std::result::Result<PathBuf, String>) {
+        let name = display_name(path.file_name().unwrap_or_default());
+
+        let orig = match res {
+            Ok(orig) => orig,
+            Err(error) => {
+                self.error = Some(format!("{}: {}", name, error));
+                return;
+            },
+        };
+
+        if let Some(dir) = path.parent() {
+            self.dir_changed(dir);
+        }
+        if let Some(dir) = orig.parent() {
+            self.dir_changed(dir);
+            self.message = Some(format!("restored {} to {}", name, display_name(dir.as_os_str())));
+        }
+    }
+
+    // Drops a deleted entry from its dir. Also for trashed entries, which
+    // are gone all the same
+    fn deleted(&mut self, path: &Path, error: Option<String>, trashed: bool) {
+        if let Some(error) = error {
+            self.error = Some(format!("{}: {}", display_name(path.file_name().unwrap_or_default()), error));
+            return;
+        }
+
+        // Not left to the watcher, it may not be running. The sizes of the
+        // dirs above are counted again along with it
+        self.dir_sizes.retain(|p, _| !p.starts_with(path));
+        if let Some(dir) = path.parent() {
+            self.dir_changed(dir);
+        }
+
+        self.message = Some(format!("{} {}",
+            match trashed {
+                true  => "trashed",
+                false => "deleted",
+            },
+            display_name(path.file_name().unwrap_or_default())
+        ));
+        self.root.clear();
+    }
+
+    // Puts the marked entries, or the one under the cursor, on the
+    // clipboard for the next paste to copy, or move if `cut`
+    fn yank(&mut self, cut: bool) {
+        let cv = match self.get_dir_mut(0) {
+            Some(cv) => cv,
+            None => return,
+        };
+
+        let paths = cv.marked_paths();
+        if paths.is_empty() {
+            return;
+        }
+        cv.clear_marks();
+
+        let what = match paths.len() {
+            1 => display_name(paths[0].file_name().unwrap_or_default()),
+            n => format!("{} entries", n),
+        };
+        self.message = Some(format!("{} {}", match cut {
+            true  => "cut",
+            false => "yanked",
+        }, what));
+
+        self.clipboard = Some(Clipboard {
+            paths: paths,
+            cut: cut,
+        });
+        self.root.clear();
+    }
+
+    // Copies or moves the clipboard into the current dir. Cut entries are
+    // gone from the clipboard once pasted, yanked ones can be pasted again
+    fn paste(&mut self) {
+        if self.paste.is_some() {
+            self.error = Some(String::from("a paste is already running"));
+            return;
+        }
+
+        let dest = match self.get_dir(0) {
+            Some(cv) => cv.dir.clone(),
+            None => return,
+        };
+
+        let cut = match self.clipboard {
+            Some(ref clipboard) => clipboard.cut,
+            None => {
+                self.error = Some(String::from("nothing to paste"));
+                return;
+            },
+        };
+        let paths = match cut {
+            true  => self.clipboard.take().unwrap().paths,
+            false => self.clipboard.as_ref().unwrap().paths.clone(),
+        };
+
+        let (cancel, answers) = ops::paste(paths, dest, cut, self.on_conflict, self.event_sender.clone());
+
+        self.paste = Some(Paste {
+            cut: cut,
+            progress: ops::Progress::default(),
+            pasted: 0,
+            skipped: 0,
+            dirs: HashSet::new(),
+            conflict: None,
+            answers: answers,
+            cancel: cancel,
+        });
+    }
+
+    // Asks what to do about the name the paste found taken, once no other
+    // prompt is in the way
+    fn ask_conflict(&mut self) {
+        if self.prompt.is_some() {
+            return;
+        }
+
+        let dst = match self.paste.as_mut().and_then(|paste| paste.conflict.take()) {
+            Some(dst) => dst,
+            None => return,
+        };
+
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Conflict,
+            text: format!(
+                "{} exists: o)verwrite s)kip r)ename n)ewer wins, uppercase for all",
+                display_name(dst.file_name().unwrap_or_default())
+            ),
+            error: None,
+        });
+    }
+
+    fn pasted(&mut self, src: PathBuf, dst: PathBuf, error: Option<String>) {
+        let paste = match self.paste {
+            Some(ref mut paste) => paste,
+            None => return,
+        };
+
+        // What was cut short by cancelling isn't an error
+        if let Some(error) = error {
+            if !paste.cancel.load(Ordering::Relaxed) {
+                self.error = Some(format!("{}: {}", display_name(src.file_name().unwrap_or_default()), error));
+            }
+            return;
         }
-    }
 
-    fn set_geo(&mut self, ngeo: Rect) {
-        match self {
-            View::Dir(ref mut dv) => dv.geo = ngeo,
-            View::File(ref mut fv) => fv.geo = ngeo,
+        paste.pasted += 1;
+        paste.dirs.extend(dst.parent().map(|dir| dir.to_owned()));
+        if paste.cut {
+            paste.dirs.extend(src.parent().map(|dir| dir.to_owned()));
         }
     }
-        
-    fn as_dir(&self) -> Option<&DirView> {
-        match self {
-            View::Dir(ref dv) => Some(dv),
-            _ => None,
+
+    // Rescans the dirs the paste changed
+    fn paste_done(&mut self) {
+        let paste = match self.paste.take() {
+            Some(paste) => paste,
+            None => return,
+        };
+
+        for dir in paste.dirs.iter() {
+            self.dir_changed(dir);
         }
-    }
 
-    fn as_file(&self) -> Option<&FileView> {
-        match self {
-            View::File(ref fv) => Some(fv),
-            _ => None,
+        let mut message = match paste.cancel.load(Ordering::Relaxed) {
+            true  => String::from("paste cancelled"),
+            false => format!("{} {} {}",
+                match paste.cut {
+                    true  => "moved",
+                    false => "copied",
+                },
+                paste.pasted,
+                match paste.pasted {
+                    1 => "entry",
+                    _ => "entries",
+                },
+            ),
+        };
+        if paste.skipped > 0 {
+            message.push_str(&format!(", skipped {}", paste.skipped));
         }
+        self.message = Some(message);
+        self.root.clear();
     }
 
-    fn as_dir_mut(&mut self) -> Option<&mut DirView> {
-        match self {
-            View::Dir(ref mut dv) => Some(dv),
-            _ => None,
+    fn cancel_sizes(&mut self) {
+        for cancel in self.size_jobs.drain(..) {
+            cancel.store(true, Ordering::Relaxed);
         }
-    }
 
-    fn as_file_mut(&mut self) -> Option<&mut FileView> {
-        match self {
-            View::File(ref mut fv) => Some(fv),
-            _ => None,
+        // Totals that were being counted again stay out of date
+        let sizes = &self.dir_sizes;
+        let recounts: Vec<PathBuf> = self.sizing.drain()
+            .filter(|dir| sizes.contains_key(dir))
+            .collect();
+        for dir in recounts {
+            self.forget_sizes(&dir);
         }
-    }
-}
 
-impl From<DirView> for View {
-    fn from(dv: DirView) -> View {
-        View::Dir(dv)
+        for view in self.views.values_mut() {
+            if let View::Dir(ref mut dv) = view {
+                dv.clear_partial_sizes();
+            }
+        }
     }
-}
 
-impl From<FileView> for View {
-    fn from(fv: FileView) -> View {
-        View::File(fv)
+    fn recount_size(&mut self, dir: &Path) {
+        self.sizing.insert(dir.to_owned());
+        self.size_jobs.push(size::spawn(vec![dir.to_owned()], self.event_sender.clone()));
     }
-}
 
-impl<'a> Drawable<&ColorMap<'a>> for View { 
-    fn get_geo(&self) -> Rect {
-        match self {
-            View::Dir(dv) => dv.get_geo(),
-            View::File(fv) => fv.get_geo(),
+    // Drops the totals of `dir` and the dirs above it, also where they're
+    // shown
+    fn forget_sizes(&mut self, dir: &Path) {
+        self.dir_sizes.retain(|p, _| !dir.starts_with(p));
+        for ancestor in dir.ancestors() {
+            let parent = ancestor.parent().and_then(|p| self.views.get_mut(p));
+            if let Some(dv) = parent.and_then(|v| v.as_dir_mut()) {
+                dv.clear_dir_size(ancestor);
+            }
         }
     }
 
-    fn draw(&mut self, d: &mut impl Canvas, c: &ColorMap) {
-        match self {
-            View::Dir(ref mut dv) => dv.draw(d, c),
-            View::File(ref mut fv) => fv.draw(d, c),
+    // Adds `change` to the known totals of the dirs above `dir`
+    fn adjust_sizes_above(&mut self, dir: &Path, change: i64) {
+        for ancestor in dir.ancestors().skip(1) {
+            let total = match self.dir_sizes.get_mut(ancestor) {
+                Some(total) => {
+                    *total = total.saturating_add_signed(change);
+                    *total
+                },
+                None => continue,
+            };
+
+            let parent = ancestor.parent().and_then(|p| self.views.get_mut(p));
+            if let Some(dv) = parent.and_then(|v| v.as_dir_mut()) {
+                dv.set_dir_size(ancestor, total);
+            }
         }
     }
-}
-
-impl<'a> Nv<'a> {
-    fn new( geo: Rect, 
-            dir: PathBuf, 
-            colors: ColorMap<'a>, 
-            binds: KeyBinds, ) -> Self { 
 
-        let dir = dir.canonicalize().unwrap();
+    fn dir_changed(&mut self, dir: &Path) {
+        if trash::files_dir().as_deref() == Some(dir) {
+            self.trash = None;
+        }
 
-        Self {
-            root: RootWin::new(geo.clone()),
+        // The totals of the dir and everything above it are out of date.
+        // If the dir's own is known it's counted again, which corrects the
+        // ones above once it's done
+        match self.dir_sizes.contains_key(dir) {
+            true  => self.recount_size(dir),
+            false => self.forget_sizes(dir),
+        }
+        self.sizes_cancelled = false;
 
-            views: {
-                let mut vm = ViewMap::new();
-                vm.insert(
-                    dir.clone().into(),
-                    DirView::new(
-                        Rect::new(0, 0, geo.w, geo.h),
-                        dir.clone()
-                    ).into()
-                );
-                vm
-            },
-            cur_path: dir,
+        // Any change might change the status of the repository
+        if let Some(Some(root)) = self.git_roots.get(dir).cloned() {
+            self.fetch_git_status(root);
+        }
 
-            views_shown: 3,
+        match self.views.get_mut(dir).and_then(|v| v.as_dir_mut()) {
+            Some(dv) => dv.scan_dir(self.event_sender.clone(), self.hide_ignored),
+            None => return,
+        }
 
-            colors: colors,
-            binds: binds,
+        // Previews of files in the dir might be out of date too
+        let stale_files: Vec<PathBuf> = self.views.iter()
+            .filter(|(p, v)| v.as_file().is_some() && p.parent() == Some(dir))
+            .map(|(p, _)| p.clone())
+            .collect();
+        for path in stale_files {
+            self.views.remove(&path);
         }
+
+        self.ensure_populated(1);
+        self.root.clear();
     }
 
+    // Returns the view of `dir`, scanning it if it wasn't already
+    fn ensure_dir_view(&mut self, dir: &Path) -> Option<&mut DirView> {
+        if !self.views.contains_key(dir) {
+            if !dir.is_dir() {
+                return None;
+            }
 
-    fn start(&mut self) -> Result {
-        let mut orig_pos = self.root.abs_cursor_pos();
-        let adjusted     = self.root.ensure_geo();
+            let geo = self.get_view(0)?.get_geo();
+            let mut dv = DirView::new(geo, dir, self.scroll_off);
 
-        orig_pos.1 -= adjusted.1;
+            dv.scan_dir(self.event_sender.clone(), self.hide_ignored);
+            dv.sort(self.sort_order_for(dir));
+            match self.positions.get(dir) {
+                Some((name, scroll)) => dv.restore_position(name, scroll),
+                None => dv.select_first(),
+            }
 
-        {
-            let cv = self.get_dir_mut(0).unwrap();
+            self.views.insert(dir.to_owned(), dv.into());
+        }
 
-            cv.scan_dir();
-            cv.sort(SortOrder::Name);
-            cv.select_first();
+        self.views.get_mut(dir)?.as_dir_mut()
+    }
+
+    // Moves from a dir entered through a symlink to where it really is
+    fn resolve_cur_path(&mut self) {
+        let real = match self.cur_path.canonicalize() {
+            Ok(real) => real,
+            Err(_) => return,
+        };
 
+        if real != self.cur_path {
+            self.goto_entry(&real);
+            self.cur_path = real;
             self.ensure_populated(1);
-            self.ensure_populated(-(self.views_shown as isize-2));
         }
+    }
 
-        self.root.cursor().hide().unwrap();
-
-        // initial draw
-        self.draw()?;
+    // Selects the target of the selected symlink
+    fn follow_link(&mut self) {
+        let entry = match self.get_dir(0).and_then(|cv| cv.sel_entry()) {
+            Some(entry) => entry,
+            None => return,
+        };
 
-        // let mut stdin = self.root.input().read_async().bytes();
-        loop {
-            let c = self.root.input().read_char().unwrap();
+        if !entry.is_symlink() {
+            self.error = Some(String::from("not a symlink"));
+            return;
+        }
 
-            match self.binds[&c] {
-                Action::Quit => break,
+        match entry.path.canonicalize() {
+            Ok(target) => {
+                self.goto_entry(&target);
+                self.root.clear();
+            },
+            Err(_) => self.error = Some(String::from("broken symlink")),
+        }
+    }
 
-                Action::MoveDown(..) |
-                Action::MoveUp(..) => {
-                    let n = match self.binds[&c] {
-                        Action::MoveDown(nn) => nn as isize,
-                        Action::MoveUp(nn)   => -(nn as isize),
-                        _ => unreachable!(),
-                    };
+    // Makes `path` the selected entry of the current column, selecting the
+    // way down to it in every column leading up to it
+    fn goto_entry(&mut self, path: &Path) {
+        let ancestors: Vec<&Path> = path.ancestors().collect();
 
-                    let cv = self.get_dir_mut(0).unwrap();
+        for pair in ancestors.windows(2).rev() {
+            let (child, parent) = (pair[0], pair[1]);
 
-                    if cv.inc_sel(n as isize) != 0 {
+            let name = match child.file_name() {
+                Some(name) => name,
+                None => continue,
+            };
 
-                        cv.ensure_sel_in_view();
-                        self.ensure_populated(1);
+            if let Some(dv) = self.ensure_dir_view(parent) {
+                // The entry might be hidden by the column's filter
+                if !dv.select_by_name(name) && dv.filter.is_some() {
+                    dv.set_filter(None);
+                    dv.select_by_name(name);
+                }
+                dv.ensure_sel_in_view();
+            }
+        }
 
-                        self.root.clear();
-                    }
-                },
+        if let Some(parent) = path.parent() {
+            self.cur_path = parent.to_owned();
+        }
 
-                Action::MoveLeft(..) |
-                Action::MoveRight(..) => {
-                    let n = match self.binds[&c] {
-                        Action::MoveLeft(nn) => -(nn as isize),
-                        Action::MoveRight(nn)  => nn as isize,
-                        _ => unreachable!(),
-                    };
+        self.ensure_populated(1);
+        self.ensure_populated(-(self.views_shown as isize-2));
+    }
 
-                    let steps = self.ensure_populated(n);
-                    if steps != 0 {
-                        self.cur_path = 
-                            self.get_view(steps).unwrap().path().to_owned();
+    fn start_search_prompt(&mut self) {
+        let (orig_sel, orig) = match self.get_dir(0) {
+            Some(cv) => (cv.sel[0], cv.search.clone()),
+            None => return,
+        };
 
-                        self.root.clear();
-                    }
-                },
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Search {
+                orig_sel: orig_sel,
+                orig: orig,
+            },
+            text: String::new(),
+            error: None,
+        });
+    }
 
-                _   => (),
+    fn set_cur_search(&mut self, sel: Option<usize>, search: Option<Search>) {
+        if let Some(cv) = self.get_dir_mut(0) {
+            if let Some(sel) = sel {
+                cv.sel[0] = sel;
+                cv.ensure_sel_in_view();
             }
-            self.draw()?;
+            cv.search = search;
         }
-
-        self.root.cursor().show().unwrap();
-
-        self.end(orig_pos)
+        self.ensure_populated(1);
+        self.root.clear();
     }
 
-    fn draw(&mut self) -> Result {
-        let shown = self.views_shown;
-        let pre = (shown as isize)-2;
+    // Jumps to the next (or previous) match of the current column's search,
+    // falling back to the last search made
+    fn search_next(&mut self, forward: bool) {
+        let last_search = self.last_search.clone();
 
-        let width = ((self.root.geo.w as f32 -1.0) / shown as f32).floor() as u16;
-        let height = self.root.geo.h;
-        
-        let a_ofs = -pre;
-        let b_ofs = 1;
+        let (found, old_sel, new_sel) = match self.get_dir_mut(0) {
+            Some(cv) => {
+                let search = match cv.search.clone().or(last_search) {
+                    Some(search) => search,
+                    None => return,
+                };
+                let old_sel = cv.sel[0];
+                let n = cv.sorted_indices.len().max(1);
+                let from = match forward {
+                    true  => old_sel + 1,
+                    false => old_sel + n - 1,
+                };
 
-        for ofs in a_ofs..(b_ofs+1) {
-            if let Some(path) = self.traverse_dirs(ofs) {
-                if let Some(view) = self.views.get_mut(&path) {
-                    view.set_geo(Rect {
-                        x: (ofs+pre) as u16 * width, 
-                        y: 0,
-                        w: width,
-                        h: height,
-                    });
-                    self.root.draw(view, &self.colors);
-                }
-            }
+                let found = cv.select_match(&search, from % n, forward);
+                cv.search = Some(search);
+
+                (found, old_sel, cv.sel[0])
+            },
+            None => return,
+        };
+
+        if !found {
+            self.error = Some("no match".to_owned());
+        } else if forward && new_sel <= old_sel {
+            self.message = Some("search hit bottom, continuing at top".to_owned());
+        } else if !forward && new_sel >= old_sel {
+            self.message = Some("search hit top, continuing at bottom".to_owned());
         }
 
-        Ok(())
+        self.ensure_populated(1);
+        self.root.clear();
+    }
+
+    fn set_cur_filter(&mut self, filter: Option<Filter>) {
+        if let Some(cv) = self.get_dir_mut(0) {
+            cv.set_filter(filter);
+        }
+        self.ensure_populated(1);
+        self.root.clear();
     }
 
     fn end(&mut self, pos: (u16, u16)) -> Result {
@@ -748,7 +3908,7 @@ impl<'a> Nv<'a> {
 
         if lvl_ofs > 0 {
             for _ in 0..lvl_ofs {
-                path.push(self.views.get(&path)?.as_dir()?.sel_file_name());
+                path.push(self.views.get(&path)?.as_dir()?.sel_file_name()?);
             }
         }
 
@@ -784,46 +3944,60 @@ impl<'a> Nv<'a> {
     }
 
     #[inline]
+    #[allow(dead_code)]
     fn get_file(&self, lvl_ofs: isize) -> Option<&FileView> {
         self.get_view(lvl_ofs)?.as_file()
     }
 
     #[inline]
+    #[allow(dead_code)]
     fn get_file_mut(&mut self, lvl_ofs: isize) -> Option<&mut FileView> {
         self.get_view_mut(lvl_ofs)?.as_file_mut()
     }
     
+    // Makes the missing views up to `ofs` levels right of the current dir,
+    // or left of it if negative. Returns how many levels there are views
+    // for, fewer than asked if it ran out
     fn ensure_populated(&mut self, ofs: isize) -> isize {
         // TODO: manually traverse dirs to not retraverse at every iteration
         
+        // Each loop stops at the first level without a view, which is how
+        // far one can go. There's nothing to the right of an empty or
+        // loading dir and nothing to the left of /
         if ofs > 0 {
             for i in 1..(ofs+1) {
-                if let Some(child_path) = self.traverse_dirs(i) {
-
-                    if self.views.contains_key(&child_path) {
-                        continue;
-                    }
-
-                    if let Some(parent_dir) = self.get_dir(i-1) {
-                        match parent_dir.make_selected_view() {
+                let child_path = match self.traverse_dirs(i) {
+                    Some(child_path) => child_path,
+                    None => return i - 1,
+                };
 
-                            Some(View::Dir(mut child)) => {
-                                child.scan_dir();
-                                child.sort(SortOrder::Name);
-                                child.select_first();
+                if self.views.contains_key(&child_path) {
+                    continue;
+                }
 
-                                self.views.insert(child_path, child.into()); 
-                            }
+                let parent_dir = match self.get_dir(i-1) {
+                    Some(parent_dir) => parent_dir,
+                    None => return i - 1,
+                };
 
-                            Some(fv@View::File(..)) => {
-                                self.views.insert(child_path, fv);
-                            },
+                match parent_dir.make_selected_view() {
 
-                            _ => return i,
+                    Some(View::Dir(mut child)) => {
+                        child.scan_dir(self.event_sender.clone(), self.hide_ignored);
+                        child.sort(self.sort_order_for(&child_path));
+                        match self.positions.get(&child_path) {
+                            Some((name, scroll)) => child.restore_position(name, scroll),
+                            None => child.select_first(),
                         }
-                    } else {
-                        return i;
+
+                        self.views.insert(child_path, child.into()); 
                     }
+
+                    Some(fv@View::File(..)) => {
+                        self.views.insert(child_path, fv);
+                    },
+
+                    None => return i - 1,
                 }
             }
 
@@ -832,19 +4006,25 @@ impl<'a> Nv<'a> {
 
         if ofs < 0 {
             for i in (ofs..0).rev() {
-                let child = self.get_view(i+1).unwrap();
+                let child = match self.get_view(i+1) {
+                    Some(child) => child,
+                    None => return i + 1,
+                };
 
                 let mut parent_path = child.path().to_owned();
 
                 if !parent_path.pop() {
-                    return i;
+                    return i + 1;
                 }
 
                 if !self.views.contains_key(&parent_path) { 
-                    let mut parent = child.make_parent_dir_view().unwrap();
+                    let mut parent = match child.make_parent_dir_view(self.scroll_off) {
+                        Some(parent) => parent,
+                        None => return i + 1,
+                    };
 
-                    parent.scan_dir();
-                    parent.sort(SortOrder::Name);
+                    parent.scan_dir(self.event_sender.clone(), self.hide_ignored);
+                    parent.sort(self.sort_order_for(&parent_path));
                     parent.select_by_name(child.file_name());
                     parent.ensure_sel_in_view();
 
@@ -859,6 +4039,77 @@ impl<'a> Nv<'a> {
     }
 }
 
+fn term_modes() -> Option<libc::termios> {
+    unsafe {
+        let mut modes: libc::termios = std::mem::zeroed();
+        match libc::tcgetattr(libc::STDIN_FILENO, &mut modes) {
+            0 => Some(modes),
+            _ => None,
+        }
+    }
+}
+
+fn set_term_modes(modes: &libc::termios) {
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, modes);
+    }
+}
+
+// Forwards key presses to the main loop from a separate thread.
+// Nothing is read while `paused`, the keys are left for whoever has the
+// terminal then
+fn read_keys(events: Sender<Event>, paused: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        let mut pending = vec![];
+
+        loop {
+            // Waits in short steps to notice when paused, and reads straight
+            // from the fd so nothing is buffered away from the others
+            let mut fds = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut fds, 1, KEY_POLL_INTERVAL.as_millis() as libc::c_int) };
+            let interrupted = || std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted;
+
+            match ready {
+                0 => continue,
+                _ if ready < 0 && interrupted() => continue,
+                _ if ready < 0 => return,
+                _ => (),
+            }
+            if paused.load(Ordering::SeqCst) {
+                thread::sleep(KEY_POLL_INTERVAL);
+                continue;
+            }
+
+            let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            let n = match n {
+                n if n > 0 => n as usize,
+                _ if n < 0 && interrupted() => continue,
+                _ => return,
+            };
+
+            // A multibyte char might be split across reads
+            pending.extend_from_slice(&buf[..n]);
+            let (keys, used) = keys::parse(&pending);
+
+            for c in keys {
+                if events.send(Event::Key(c)).is_err() {
+                    return;
+                }
+            }
+            pending.drain(..used);
+
+            // No char is longer than 4 bytes, drop the garbage
+            if pending.len() >= 4 {
+                pending.clear();
+            }
+        }
+    });
+}
 
 fn main() {
 
@@ -876,6 +4127,16 @@ fn main() {
         bg_color: None,
         attrs: vec![Attribute::Reverse],
     });
+    colors.insert("Marked", ObjectStyle {
+        fg_color: None,
+        bg_color: Some(Color::AnsiValue(238)),
+        attrs: vec![],
+    });
+    colors.insert("Edit", ObjectStyle {
+        fg_color: None,
+        bg_color: None,
+        attrs: vec![Attribute::Underlined],
+    });
     colors.insert("Directory", ObjectStyle {
         fg_color: Some(Color::Blue),
         bg_color: None,
@@ -886,17 +4147,232 @@ fn main() {
         bg_color: None,
         attrs: vec![],
     });
+    colors.insert("Symlink", ObjectStyle {
+        fg_color: Some(Color::Cyan),
+        bg_color: None,
+        attrs: vec![],
+    });
+    colors.insert("BrokenLink", ObjectStyle {
+        fg_color: Some(Color::Red),
+        bg_color: None,
+        attrs: vec![Attribute::CrossedOut],
+    });
+    colors.insert("GitModified", ObjectStyle {
+        fg_color: Some(Color::Yellow),
+        bg_color: None,
+        attrs: vec![Attribute::Bold],
+    });
+    colors.insert("GitStaged", ObjectStyle {
+        fg_color: Some(Color::Green),
+        bg_color: None,
+        attrs: vec![Attribute::Bold],
+    });
+    colors.insert("GitUntracked", ObjectStyle {
+        fg_color: Some(Color::Magenta),
+        bg_color: None,
+        attrs: vec![],
+    });
+    colors.insert("GitIgnored", ObjectStyle {
+        fg_color: None,
+        bg_color: None,
+        attrs: vec![Attribute::Dim],
+    });
+    colors.insert("GitConflicted", ObjectStyle {
+        fg_color: Some(Color::Red),
+        bg_color: None,
+        attrs: vec![Attribute::Bold],
+    });
+    colors.insert("UsageBar", ObjectStyle {
+        fg_color: Some(Color::Green),
+        bg_color: None,
+        attrs: vec![],
+    });
+    colors.insert("SearchMatch", ObjectStyle {
+        fg_color: Some(Color::Yellow),
+        bg_color: None,
+        attrs: vec![Attribute::Underlined],
+    });
+    colors.insert("Info", ObjectStyle {
+        fg_color: None,
+        bg_color: None,
+        attrs: vec![Attribute::Dim],
+    });
+    colors.insert("StatusLine", ObjectStyle {
+        fg_color: None,
+        bg_color: None,
+        attrs: vec![],
+    });
+    colors.insert("Error", ObjectStyle {
+        fg_color: Some(Color::Red),
+        bg_color: None,
+        attrs: vec![Attribute::Bold],
+    });
+
+    // The built-in entry styles above are only defaults, LS_COLORS replaces
+    // them so listings look like ls
+    let ls_colors = std::env::var("LS_COLORS")
+        .map(|ls_colors| lscolors::parse(&ls_colors))
+        .unwrap_or_default();
+    for (name, style) in ls_colors.iter() {
+        colors.insert(name.as_str(), style.clone());
+    }
 
     let mut binds = HashMap::new();
 
     // TODO: Make keybinds config parser
     binds.insert('q', Action::Quit);
+    binds.insert('\x03', Action::Quit); // ctrl-c
     binds.insert('j', Action::MoveDown(1));
     binds.insert('k', Action::MoveUp(1));
     binds.insert('h', Action::MoveLeft(1));
     binds.insert('l', Action::MoveRight(1));
+    binds.insert(keys::DOWN, Action::MoveDown(1));
+    binds.insert(keys::UP, Action::MoveUp(1));
+    binds.insert(keys::LEFT, Action::MoveLeft(1));
+    binds.insert(keys::RIGHT, Action::MoveRight(1));
+    binds.insert(keys::PAGE_DOWN, Action::PageDown);
+    binds.insert(keys::PAGE_UP, Action::PageUp);
+    binds.insert(keys::HOME, Action::GoFirst);
+    binds.insert(keys::END, Action::GoLast);
+    binds.insert('\x06', Action::PageDown);     // ctrl-f
+    binds.insert('\x02', Action::PageUp);       // ctrl-b
+    binds.insert('\x04', Action::HalfPageDown); // ctrl-d
+    binds.insert('\x15', Action::HalfPageUp);   // ctrl-u
+    binds.insert('g', Action::GoFirst);
+    binds.insert('G', Action::GoLast);
+    binds.insert('M', Action::GoMiddle);
+    binds.insert('f', Action::Filter);
+    binds.insert('F', Action::ClearFilter);
+    binds.insert('/', Action::Search);
+    binds.insert('n', Action::SearchNext);
+    binds.insert('N', Action::SearchPrev);
+    binds.insert('\x14', Action::Find); // ctrl-t
+    binds.insert('i', Action::ToggleInfo);
+    binds.insert('\x1b', Action::CancelScan); // esc
+    binds.insert('L', Action::FollowLink);
+    binds.insert('I', Action::ToggleIgnored);
+    binds.insert('S', Action::ComputeSizes);
+    binds.insert('s', Action::CycleSort);
+    binds.insert('u', Action::DiskUsage);
+    binds.insert('D', Action::Delete);
+    binds.insert('d', Action::Trash);
+    binds.insert('r', Action::Rename);
+    binds.insert('B', Action::BulkRename);
+    binds.insert('b', Action::BatchRename);
+    binds.insert('a', Action::NewFile);
+    binds.insert('A', Action::NewDir);
+    binds.insert('z', Action::NewSymlink);
+    binds.insert('Z', Action::NewHardlink);
+    binds.insert('T', Action::GotoTrash);
+    binds.insert('R', Action::Restore);
+    binds.insert(' ', Action::ToggleMark);
+    binds.insert('y', Action::Yank);
+    binds.insert('x', Action::Cut);
+    binds.insert('p', Action::Paste);
+
+    let mut column_info = HashMap::new();
+
+    // TODO: Make column info configurable
+    column_info.insert(0, vec![InfoField::Size, InfoField::MTime, InfoField::Perms]);
+
+    // -P resolves symlinks like `cd -P` does,
+    // -s computes the sizes of the dirs in the current column automatically
+    let physical = std::env::args().skip(1).any(|arg| arg == "-P");
+    let auto_sizes = std::env::args().skip(1).any(|arg| arg == "-s");
+
+    // --scroll-off=N keeps N rows between the selection and the column edges
+    let scroll_off = std::env::args().skip(1)
+        .find_map(|arg| arg.strip_prefix("--scroll-off=")?.parse().ok())
+        .unwrap_or(DEFAULT_SCROLL_OFF);
+
+    // --on-conflict=overwrite|skip|rename|newer decides about the names
+    // taken when pasting instead of asking. A typo mustn't quietly bring
+    // the questions back
+    let on_conflict = std::env::args().skip(1)
+        .find_map(|arg| arg.strip_prefix("--on-conflict=").map(String::from))
+        .map(|name| match ops::Policy::from_name(&name) {
+            Some(policy) => policy,
+            None => {
+                eprintln!("nv: unknown --on-conflict policy \"{}\", expected overwrite, skip, rename or newer", name);
+                std::process::exit(2);
+            },
+        });
 
-    Nv::new(Rect::new(cpos.0, cpos.1, 90, 5), PathBuf::from(r"./"), colors, binds)
+    Nv::new(Rect::new(cpos.0, cpos.1, 90, 6), PathBuf::from(r"./"), colors, binds, column_info, physical, auto_sizes, scroll_off, on_conflict)
         .start()
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A view of a temporary dir with an empty file for each name
+    fn dir_view(test: &str, names: &[&str]) -> DirView {
+        let dir = TestDir::new(test);
+        for name in names {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let entries = fs::read_dir(&dir).unwrap()
+            .map(|e| Entry::from_dir_entry(e.unwrap()))
+            .collect();
+
+        let mut dv = DirView::new(Rect::new(0, 0, 40, 10), &dir, 0);
+        dv.replace_entries(entries);
+        dv
+    }
+
+    fn marked_names(dv: &DirView) -> Vec<String> {
+        dv.marked_paths().iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn marks_in_listing_order() {
+        let mut dv = dir_view("mark-order", &["a", "b", "c"]);
+        assert_eq!(marked_names(&dv), ["a"]);
+
+        for sel in [2, 0, 1] {
+            dv.set_sel(sel);
+            dv.toggle_mark();
+        }
+        assert_eq!(marked_names(&dv), ["a", "b", "c"]);
+
+        dv.toggle_mark();
+        assert_eq!(marked_names(&dv), ["a", "c"]);
+    }
+
+    #[test]
+    fn filter_keeps_the_cursor_and_marks() {
+        let mut dv = dir_view("mark-filter", &["a", "b", "c", "d"]);
+        dv.set_sel(1);
+        dv.toggle_mark();
+        dv.set_sel(3);
+        dv.toggle_mark();
+        dv.set_sel(1);
+
+        // Hides the cursor's entry and one of the marks
+        dv.set_filter(Some(Filter::new(FilterKind::Regex, "[cd]").unwrap()));
+        assert_eq!(dv.sel, [1]);
+        assert_eq!(dv.mark_count(), 2);
+        assert_eq!(marked_names(&dv), ["b", "d"]);
+
+        dv.set_filter(None);
+        assert_eq!(marked_names(&dv), ["b", "d"]);
+    }
+
+    #[test]
+    fn rescan_keeps_marks_by_name() {
+        let mut dv = dir_view("mark-rescan", &["a", "b", "c"]);
+        dv.set_sel(2);
+        dv.toggle_mark();
+
+        // b is gone and the rest come in another order
+        let entries = dir_view("mark-rescan", &["c", "a"]).entries;
+        dv.replace_entries(entries);
+        assert_eq!(marked_names(&dv), ["c"]);
+        assert_eq!(dv.sel_path().unwrap().file_name(), Some(OsStr::new("c")));
+    }
+}
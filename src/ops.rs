@@ -0,0 +1,733 @@
+use std::ffi::{
+    CString,
+    OsString,
+};
+use std::fs::{
+    self,
+    File,
+    OpenOptions,
+};
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{
+    MetadataExt,
+    OpenOptionsExt,
+    symlink,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, Sender},
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{
+    Event,
+    io_error_string,
+    trash,
+};
+
+// how often the progress of a paste is reported
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// how much of a file is copied at a time
+const COPY_BUF_LEN: usize = 256 * 1024;
+
+// Deletes `paths` for good in the background, dirs with everything in
+// them. Symlinks are removed, not what they point to, and trashed entries
+// take their .trashinfo files with them. Each path is reported as
+// `Event::Deleted` with the error if it couldn't be deleted
+pub fn delete(paths: Vec<PathBuf>, events: Sender<Event>) {
+    thread::spawn(move || {
+        for path in paths {
+            let res = remove(&path);
+            if res.is_ok() {
+                trash::forget(&path);
+            }
+
+            let error = res.err().map(|e| io_error_string(&e));
+            if events.send(Event::Deleted(path, error)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+// Removes `path`, dirs with everything in them
+fn remove(path: &Path) -> io::Result<()> {
+    match path.symlink_metadata()?.is_dir() {
+        true  => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    }
+}
+
+// What to do with a pasted entry whose name is taken
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Overwrite,
+    Skip,
+    Rename, // with the first free numeric suffix
+    OverwriteIfNewer,
+}
+
+impl Policy {
+    // As given to --on-conflict
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "overwrite" => Some(Policy::Overwrite),
+            "skip"      => Some(Policy::Skip),
+            "rename"    => Some(Policy::Rename),
+            "newer"     => Some(Policy::OverwriteIfNewer),
+            _ => None,
+        }
+    }
+}
+
+// The answer to `Event::PasteConflict`
+pub struct Answer {
+    pub policy: Policy,
+    pub all: bool, // whether it goes for the rest of the conflicts too
+}
+
+// How far a paste has got
+#[derive(Clone, Default)]
+pub struct Progress {
+    pub name: OsString,  // of the file being copied
+    pub file_done: u64,
+    pub file_size: u64,
+    pub done: u64,       // bytes of all the files
+    pub total: u64,
+}
+
+// Copies `paths` into the dir `dest` in the background, or moves them
+// there if `cut`. Dirs are copied with everything in them, symlinks as
+// symlinks, keeping the permissions and timestamps.
+// Taken names are dealt with by `policy`, or asked about with
+// `Event::PasteConflict` if there is none, to be answered through the
+// returned sender. An answer of None cancels the paste.
+// The progress is reported as `Event::PasteProgress`, each path as
+// `Event::Pasted` with the error if it failed or `Event::PasteSkipped`,
+// and the end as `Event::PasteDone`. Setting the returned flag stops the
+// work
+pub fn paste(
+    paths: Vec<PathBuf>,
+    dest: PathBuf,
+    cut: bool,
+    policy: Option<Policy>,
+    events: Sender<Event>,
+) -> (Arc<AtomicBool>, Sender<Option<Answer>>) {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (answer_sender, answers) = mpsc::channel();
+
+    {
+        let cancel = cancel.clone();
+
+        thread::spawn(move || {
+            let mut copier = Copier {
+                cancel: &cancel,
+                events: &events,
+                policy: policy,
+                answers: answers,
+                progress: Progress {
+                    total: paths.iter().map(|p| tree_size(p)).sum(),
+                    ..Progress::default()
+                },
+                last_send: Instant::now(),
+            };
+
+            for src in paths {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let event = match copier.paste(&src, &dest, cut) {
+                    Ok(Some(dst)) => Event::Pasted(src, dst, None),
+                    Ok(None) => Event::PasteSkipped,
+                    Err(e) => {
+                        let dst = dest.join(src.file_name().unwrap_or_default());
+                        Event::Pasted(src, dst, Some(io_error_string(&e)))
+                    },
+                };
+
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+
+            let _ = events.send(Event::PasteDone);
+        });
+    }
+
+    (cancel, answer_sender)
+}
+
+struct Copier<'a> {
+    cancel: &'a AtomicBool,
+    events: &'a Sender<Event>,
+    policy: Option<Policy>, // None asks about every conflict
+    answers: Receiver<Option<Answer>>,
+    progress: Progress,
+    last_send: Instant,
+}
+
+impl Copier<'_> {
+    // Pastes `src` into the dir `dest`, dealing with a taken name first.
+    // Returns where it went, or None if it was skipped
+    fn paste(&mut self, src: &Path, dest: &Path, cut: bool) -> io::Result<Option<PathBuf>> {
+        let meta = src.symlink_metadata()?;
+
+        // Copying a dir into itself would never end
+        if meta.is_dir() && dest.canonicalize()?.starts_with(src.canonicalize()?) {
+            return Err(io::Error::other("can't paste a dir into itself"));
+        }
+
+        let dst = dest.join(src.file_name().unwrap_or_default());
+        if dst.symlink_metadata().is_err() {
+            self.transfer(src, &dst, cut)?;
+            return Ok(Some(dst));
+        }
+
+        let policy = match self.policy {
+            Some(policy) => policy,
+            None => self.ask(&dst)?,
+        };
+
+        match resolve(src, &dst, policy, cut)? {
+            Some(Target::Free(dst)) => {
+                self.transfer(src, &dst, cut)?;
+                Ok(Some(dst))
+            },
+            Some(Target::Replace) => {
+                self.replace(src, &dst, cut)?;
+                Ok(Some(dst))
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Asks what to do about the taken `dst`, keeping the answer for the
+    // next conflicts if it's meant for all of them
+    fn ask(&mut self, dst: &Path) -> io::Result<Policy> {
+        if self.events.send(Event::PasteConflict(dst.to_owned())).is_err() {
+            return Err(cancelled());
+        }
+
+        match self.answers.recv() {
+            Ok(Some(answer)) => {
+                if answer.all {
+                    self.policy = Some(answer.policy);
+                }
+                Ok(answer.policy)
+            },
+            _ => Err(cancelled()),
+        }
+    }
+
+    // Moves or copies `src` to the free `dst`
+    fn transfer(&mut self, src: &Path, dst: &Path, cut: bool) -> io::Result<()> {
+        if dst.symlink_metadata().is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already exists"));
+        }
+
+        if cut {
+            match fs::rename(src, dst) {
+                Ok(()) => {
+                    self.progress.done += tree_size(dst);
+                    return Ok(());
+                },
+                // Across file systems it's a copy and a delete
+                Err(ref e) if e.raw_os_error() == Some(libc::EXDEV) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Whatever was copied is taken back if it didn't all make it
+        if let Err(e) = copy(src, dst, &mut |file, size, n| self.add(file, size, n)) {
+            let _ = remove(dst);
+            return Err(e);
+        }
+
+        match cut {
+            true  => remove(src),
+            false => Ok(()),
+        }
+    }
+
+    // Puts `src` in place of the taken `dst`. It's moved or copied next to
+    // it under a temporary name first, so `dst` is only let go of once its
+    // replacement is complete. The two are then swapped by renaming, which
+    // works whatever kinds of entries they are
+    fn replace(&mut self, src: &Path, dst: &Path, cut: bool) -> io::Result<()> {
+        let new = temp_name(dst);
+        self.transfer(src, &new, cut)?;
+
+        // Back where it came from if it can't take the place of `dst`
+        let undo = |e: io::Error| {
+            let _ = match cut {
+                true  => move_tree(&new, src),
+                false => remove(&new),
+            };
+            e
+        };
+
+        let old = temp_name(dst);
+        fs::rename(dst, &old).map_err(undo)?;
+        if let Err(e) = fs::rename(&new, dst) {
+            let _ = fs::rename(&old, dst);
+            return Err(undo(e));
+        }
+
+        remove(&old).map_err(|e| io::Error::other(format!(
+            "{} is left over: {}",
+            old.file_name().unwrap_or_default().to_string_lossy(),
+            io_error_string(&e)
+        )))
+    }
+
+    // Counts `n` more bytes copied of `file`, which is `size` bytes long.
+    // Returns false to stop
+    fn add(&mut self, file: &Path, size: u64, n: u64) -> bool {
+        if n == 0 {
+            self.progress.name = file.file_name().unwrap_or_default().to_owned();
+            self.progress.file_done = 0;
+            self.progress.file_size = size;
+        }
+        self.progress.file_done += n;
+        self.progress.done += n;
+
+        if self.cancel.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        if self.last_send.elapsed() < PROGRESS_INTERVAL {
+            return true;
+        }
+        self.last_send = Instant::now();
+
+        self.events.send(Event::PasteProgress(self.progress.clone())).is_ok()
+    }
+}
+
+// What kind of entry is made by `create`
+pub enum NewEntry {
+    File,
+    Dir, // along with the missing parents
+    Symlink(PathBuf), // to the given path
+    Hardlink(PathBuf),
+}
+
+impl NewEntry {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NewEntry::File        => "file",
+            NewEntry::Dir         => "dir",
+            NewEntry::Symlink(_)  => "symlink",
+            NewEntry::Hardlink(_) => "hardlink",
+        }
+    }
+}
+
+// Makes a new entry at `path`, nothing that's already there is touched.
+// Symlinks to entries of the same dir point at them by their names only
+pub fn create(path: &Path, new: &NewEntry) -> io::Result<()> {
+    if path.symlink_metadata().is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already exists"));
+    }
+
+    match new {
+        NewEntry::File => OpenOptions::new().write(true).create_new(true).open(path).map(|_| ()),
+        NewEntry::Dir => fs::create_dir_all(path),
+        NewEntry::Symlink(target) => {
+            let target = match (target.parent() == path.parent(), target.file_name()) {
+                (true, Some(name)) => Path::new(name),
+                _ => target.as_path(),
+            };
+            symlink(target, path)
+        },
+        NewEntry::Hardlink(target) => fs::hard_link(target, path),
+    }
+}
+
+// Moves `src` to `dst`, copying it over and deleting it when they're on
+// different file systems
+pub fn move_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    match fs::rename(src, dst) {
+        Err(ref e) if e.raw_os_error() == Some(libc::EXDEV) => (),
+        res => return res,
+    }
+
+    if let Err(e) = copy(src, dst, &mut |_, _, _| true) {
+        let _ = remove(dst);
+        return Err(e);
+    }
+    remove(src)
+}
+
+// Copies `src` to `dst` with everything in it, keeping the permissions
+// and timestamps. Symlinks are copied as symlinks. `add` is called with
+// each file, its size and 0 before it's copied, then with the bytes
+// copied as they are. Stops when it returns false
+fn copy(src: &Path, dst: &Path, add: &mut dyn FnMut(&Path, u64, u64) -> bool) -> io::Result<()> {
+    let meta = src.symlink_metadata()?;
+    let ft = meta.file_type();
+
+    if ft.is_dir() {
+        fs::create_dir(dst)?;
+        for e in fs::read_dir(src)? {
+            let e = e?;
+            copy(&e.path(), &dst.join(e.file_name()), add)?;
+        }
+        // Set last so a read-only dir can still be filled
+        fs::set_permissions(dst, meta.permissions())?;
+
+    } else if ft.is_symlink() {
+        symlink(fs::read_link(src)?, dst)?;
+
+    } else if ft.is_file() {
+        copy_file(src, dst, &meta, add)?;
+        fs::set_permissions(dst, meta.permissions())?;
+
+    } else {
+        return Err(io::Error::other("can't copy special files"));
+    }
+
+    set_times(dst, &meta)
+}
+
+fn copy_file(
+    src: &Path,
+    dst: &Path,
+    meta: &fs::Metadata,
+    add: &mut dyn FnMut(&Path, u64, u64) -> bool,
+) -> io::Result<()> {
+    let mut from = File::open(src)?;
+    let mut to = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(meta.mode())
+        .open(dst)?;
+
+    if !add(src, meta.len(), 0) {
+        return Err(cancelled());
+    }
+
+    let mut buf = vec![0; COPY_BUF_LEN];
+    loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        to.write_all(&buf[..n])?;
+
+        if !add(src, meta.len(), n as u64) {
+            return Err(cancelled());
+        }
+    }
+}
+
+fn cancelled() -> io::Error {
+    io::Error::other("cancelled")
+}
+
+// Where a pasted entry goes when its name is taken
+enum Target {
+    Free(PathBuf), // a name that isn't taken
+    Replace,       // in place of the entry there
+}
+
+// Decides as `policy` says where `src` goes when `dst` is taken, None if
+// it's skipped
+fn resolve(src: &Path, dst: &Path, policy: Policy, cut: bool) -> io::Result<Option<Target>> {
+    let src_meta = src.symlink_metadata()?;
+    let dst_meta = dst.symlink_metadata()?;
+
+    // Pasted where it already is, a copy can only go next to it
+    if (src_meta.dev(), src_meta.ino()) == (dst_meta.dev(), dst_meta.ino()) {
+        return match (cut, policy) {
+            (true, _) | (false, Policy::Skip) => Ok(None),
+            (false, _) => Ok(Some(Target::Free(free_name(dst)))),
+        };
+    }
+
+    match policy {
+        Policy::Skip => return Ok(None),
+        Policy::Rename => return Ok(Some(Target::Free(free_name(dst)))),
+        Policy::OverwriteIfNewer if src_meta.modified()? <= dst_meta.modified()? => return Ok(None),
+        Policy::Overwrite | Policy::OverwriteIfNewer => (),
+    }
+
+    // What's pasted can't go along with what it overwrites
+    let src_dir = src.parent().unwrap_or(src).canonicalize()?;
+    if dst_meta.is_dir() && src_dir.starts_with(dst.canonicalize()?) {
+        return Err(io::Error::other("can't overwrite a dir with its own contents"));
+    }
+
+    Ok(Some(Target::Replace))
+}
+
+// A free hidden name next to `path` to put an entry aside under
+fn temp_name(path: &Path) -> PathBuf {
+    (0..)
+        .map(|n| path.with_file_name(format!(".nv-paste-{}-{}", std::process::id(), n)))
+        .find(|p| p.symlink_metadata().is_err())
+        .unwrap()
+}
+
+// `dst` with the first numeric suffix that isn't taken, like "name_1.ext"
+fn free_name(dst: &Path) -> PathBuf {
+    let stem = dst.file_stem().unwrap_or_default();
+
+    (1..)
+        .map(|n| {
+            let mut name = stem.to_owned();
+            name.push(format!("_{}", n));
+            if let Some(ext) = dst.extension() {
+                name.push(".");
+                name.push(ext);
+            }
+            dst.with_file_name(name)
+        })
+        .find(|path| path.symlink_metadata().is_err())
+        .unwrap()
+}
+
+// The bytes of all the files under `path`, symlinks aren't followed
+fn tree_size(path: &Path) -> u64 {
+    let meta = match path.symlink_metadata() {
+        Ok(meta) => meta,
+        Err(_) => return 0,
+    };
+
+    if !meta.is_dir() {
+        return match meta.is_file() {
+            true  => meta.len(),
+            false => 0,
+        };
+    }
+
+    match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| tree_size(&e.path()))
+            .sum(),
+        Err(_) => 0,
+    }
+}
+
+// Sets the access and modification times of `path` to those in `meta`,
+// of the link itself for symlinks
+fn set_times(path: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    let cpath = CString::new(path.as_os_str().as_bytes())?;
+    let times = [
+        libc::timespec { tv_sec: meta.atime(), tv_nsec: meta.atime_nsec() },
+        libc::timespec { tv_sec: meta.mtime(), tv_nsec: meta.mtime_nsec() },
+    ];
+
+    let res = unsafe {
+        libc::utimensat(libc::AT_FDCWD, cpath.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+    };
+
+    match res {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use crate::TestDir;
+
+    // Pastes `src` into `dest` with names taken dealt with by `policy`.
+    // Returns the result and the progress at the end
+    fn run_paste(src: &Path, dest: &Path, cut: bool, policy: Policy) -> (io::Result<Option<PathBuf>>, Progress) {
+        let cancel = AtomicBool::new(false);
+        let (events, _received) = mpsc::channel();
+        let (_answer, answers) = mpsc::channel();
+
+        let mut copier = Copier {
+            cancel: &cancel,
+            events: &events,
+            policy: Some(policy),
+            answers: answers,
+            progress: Progress {
+                total: tree_size(src),
+                ..Progress::default()
+            },
+            last_send: Instant::now(),
+        };
+        let res = copier.paste(src, dest, cut);
+        (res, copier.progress)
+    }
+
+    // Pastes `src` into `dest` overwriting what's there
+    fn paste_over(src: &Path, dest: &Path) -> io::Result<Option<PathBuf>> {
+        run_paste(src, dest, false, Policy::Overwrite).0
+    }
+
+    // A dir with a file, an empty subdir and a symlink, all with their own
+    // modes and times. Returns the path of the dir
+    fn make_tree(base: &Path) -> PathBuf {
+        let tree = base.join("tree");
+        fs::create_dir_all(tree.join("sub")).unwrap();
+        fs::write(tree.join("file"), "12345").unwrap();
+        symlink("file", tree.join("link")).unwrap();
+
+        let set = |path: &Path, mode: u32, mtime: u64| {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+            File::open(path).unwrap()
+                .set_modified(std::time::UNIX_EPOCH + Duration::from_secs(mtime))
+                .unwrap();
+        };
+        set(&tree.join("file"), 0o640, 1_000_000_000);
+        set(&tree.join("sub"), 0o700, 1_100_000_000);
+        set(&tree, 0o750, 1_200_000_000);
+        tree
+    }
+
+    // Checks that `dir` is a copy of the tree made by make_tree
+    fn check_tree(dir: &Path) {
+        let mode_and_mtime = |path: &Path| {
+            let meta = path.symlink_metadata().unwrap();
+            (meta.mode() & 0o7777, meta.mtime())
+        };
+
+        assert_eq!(names(dir), ["file", "link", "sub"]);
+        assert_eq!(fs::read_to_string(dir.join("file")).unwrap(), "12345");
+        assert_eq!(fs::read_link(dir.join("link")).unwrap(), Path::new("file"));
+        assert_eq!(mode_and_mtime(&dir.join("file")), (0o640, 1_000_000_000));
+        assert_eq!(mode_and_mtime(&dir.join("sub")), (0o700, 1_100_000_000));
+        assert_eq!(mode_and_mtime(dir), (0o750, 1_200_000_000));
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn free_name_skips_taken() {
+        let dir = TestDir::new("free-name");
+        for name in ["a.txt", "a_1.txt", "b"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        assert_eq!(free_name(&dir.join("a.txt")), dir.join("a_2.txt"));
+        assert_eq!(free_name(&dir.join("b")), dir.join("b_1"));
+        assert_eq!(free_name(&dir.join("c.tar.gz")), dir.join("c.tar_1.gz"));
+        assert_eq!(free_name(&dir.join(".hidden")), dir.join(".hidden_1"));
+    }
+
+    #[test]
+    fn overwrite_replaces_whole_entry() {
+        let dir = TestDir::new("overwrite");
+        fs::create_dir_all(dir.join("src/x")).unwrap();
+        fs::write(dir.join("src/x/new"), "new").unwrap();
+        fs::write(dir.join("src/f"), "file").unwrap();
+        fs::create_dir_all(dir.join("dest/x")).unwrap();
+        fs::write(dir.join("dest/x/old"), "old").unwrap();
+        fs::create_dir_all(dir.join("dest/f/sub")).unwrap();
+
+        let dest = dir.join("dest");
+        assert_eq!(paste_over(&dir.join("src/x"), &dest).unwrap(), Some(dest.join("x")));
+        assert_eq!(paste_over(&dir.join("src/f"), &dest).unwrap(), Some(dest.join("f")));
+
+        assert_eq!(names(&dest), ["f", "x"]);
+        assert_eq!(names(&dest.join("x")), ["new"]);
+        assert_eq!(fs::read_to_string(dest.join("f")).unwrap(), "file");
+    }
+
+    #[test]
+    fn failed_overwrite_keeps_the_old_entry() {
+        let dir = TestDir::new("overwrite-failed");
+        fs::create_dir_all(dir.join("src/x")).unwrap();
+        fs::write(dir.join("src/x/a"), "new").unwrap();
+        // Can't be copied, so the copy fails halfway through
+        let fifo = CString::new(dir.join("src/x/b").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        fs::create_dir_all(dir.join("dest/x")).unwrap();
+        fs::write(dir.join("dest/x/old"), "old").unwrap();
+
+        let dest = dir.join("dest");
+        assert!(paste_over(&dir.join("src/x"), &dest).is_err());
+
+        assert_eq!(names(&dest), ["x"]);
+        assert_eq!(names(&dest.join("x")), ["old"]);
+    }
+
+    #[test]
+    fn copy_keeps_modes_and_times() {
+        let dir = TestDir::new("copy");
+        let tree = make_tree(&dir);
+        let dest = dir.join("dest");
+        fs::create_dir(&dest).unwrap();
+
+        let (res, progress) = run_paste(&tree, &dest, false, Policy::Skip);
+        assert_eq!(res.unwrap(), Some(dest.join("tree")));
+        check_tree(&dest.join("tree"));
+        check_tree(&tree);
+        assert_eq!((progress.done, progress.total), (5, 5));
+    }
+
+    #[test]
+    fn copy_next_to_itself() {
+        let dir = TestDir::new("copy-rename");
+        let tree = make_tree(&dir);
+
+        let (res, _) = run_paste(&tree, &dir, false, Policy::Skip);
+        assert_eq!(res.unwrap(), None);
+
+        let (res, _) = run_paste(&tree, &dir, false, Policy::Overwrite);
+        assert_eq!(res.unwrap(), Some(dir.join("tree_1")));
+        check_tree(&dir.join("tree_1"));
+    }
+
+    #[test]
+    fn cut_removes_the_source() {
+        let dir = TestDir::new("cut");
+        let tree = make_tree(&dir);
+        let dest = dir.join("dest");
+        fs::create_dir(&dest).unwrap();
+
+        let (res, progress) = run_paste(&tree, &dest, true, Policy::Skip);
+        assert_eq!(res.unwrap(), Some(dest.join("tree")));
+        check_tree(&dest.join("tree"));
+        assert!(tree.symlink_metadata().is_err());
+        assert_eq!(progress.done, 5);
+    }
+
+    #[test]
+    fn cut_across_file_systems() {
+        // Needs a dir on another file system, /dev/shm usually is one
+        let other = Path::new("/dev/shm");
+        let dev = |path: &Path| path.metadata().map(|m| m.dev()).ok();
+        if dev(other).is_none() || dev(other) == dev(&std::env::temp_dir()) {
+            return;
+        }
+
+        let src_dir = TestDir::new_in(other, "cut-exdev");
+        let tree = make_tree(&src_dir);
+        let dest = TestDir::new("cut-exdev");
+
+        let (res, progress) = run_paste(&tree, &dest, true, Policy::Skip);
+        assert_eq!(res.unwrap(), Some(dest.join("tree")));
+        check_tree(&dest.join("tree"));
+        assert!(tree.symlink_metadata().is_err());
+        assert_eq!(progress.done, 5);
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::ffi::{
+    OsStr,
+    OsString,
+};
+use std::fs::{
+    self,
+    File,
+    OpenOptions,
+};
+use std::hash::{
+    BuildHasher,
+    Hasher,
+};
+use std::io::{
+    self,
+    Write,
+};
+use std::os::unix::fs::{
+    MetadataExt,
+    OpenOptionsExt,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::Command;
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use regex::{
+    Regex,
+    RegexBuilder,
+};
+
+use crate::{
+    display_name,
+    info,
+    io_error_string,
+};
+
+// Entries of one dir to be renamed, by their old and new names
+pub type Renames = Vec<(OsString, OsString)>;
+
+// Why `name` can't be the name of an entry, if it can't
+pub fn check_name(name: &str) -> Result<(), String> {
+    match name {
+        "" => Err(String::from("the name can't be empty")),
+        "." | ".." => Err(format!("{} can't be a name", name)),
+        _ if name.contains('/') => Err(String::from("names can't contain /")),
+        _ if name.contains('\0') => Err(String::from("names can't contain NUL")),
+        _ => Ok(()),
+    }
+}
+
+// How the names of a batch are rewritten: `s/regex/replacement/flags` like
+// sed, with g to replace every match and i to ignore case, or else a
+// template for the whole new name. Both can have {name}, {stem}, {ext},
+// {n} for the count from 1 ({n:3} pads it to 3 digits), {date} for today
+// and {mtime} for the day the entry was modified. Replacements can have
+// the captures as $1 or ${name}
+enum Pattern {
+    Substitute { re: Regex, replacement: String, all: bool },
+    Template(String),
+}
+
+impl Pattern {
+    fn parse(text: &str) -> Result<Self, String> {
+        let rest = match text.strip_prefix("s/") {
+            Some(rest) => rest,
+            None => return Ok(Pattern::Template(text.to_owned())),
+        };
+
+        let mut parts = rest.splitn(3, '/');
+        let (re, replacement, flags) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(re), Some(replacement), flags) => (re, replacement, flags.unwrap_or("")),
+            _ => return Err(String::from("expected s/regex/replacement/")),
+        };
+
+        let mut builder = RegexBuilder::new(re);
+        let mut all = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => all = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                },
+                _ => return Err(format!("unknown flag {}", flag)),
+            }
+        }
+
+        let re = builder.build()
+            // regex errors span several lines, only keep the actual message
+            .map_err(|e| e.to_string().lines().last().unwrap_or("").trim().to_owned())?;
+
+        Ok(Pattern::Substitute {
+            re: re,
+            replacement: replacement.to_owned(),
+            all: all,
+        })
+    }
+
+    // The new name of the `n`th entry, `name` in `dir`
+    fn apply(&self, dir: &Path, name: &str, n: usize, today: &str) -> Result<String, String> {
+        match self {
+            Pattern::Template(template) => expand(template, dir, name, n, today, false),
+            Pattern::Substitute { re, replacement, all } => {
+                let replacement = expand(replacement, dir, name, n, today, true)?;
+                let limit = match all {
+                    true  => 0,
+                    false => 1,
+                };
+                Ok(re.replacen(name, limit, replacement.as_str()).into_owned())
+            },
+        }
+    }
+}
+
+// Fills in the {fields} of `template` for the `n`th entry. In regex
+// replacements the $ of the values are escaped and ${...} is left alone
+fn expand(template: &str, dir: &Path, name: &str, n: usize, today: &str, regex: bool) -> Result<String, String> {
+    let (stem, ext) = match name.rfind('.').filter(|&i| i > 0) {
+        Some(i) => (&name[..i], &name[i+1..]),
+        None => (name, ""),
+    };
+
+    let mut out = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+
+        if regex && rest[..start].ends_with('$') {
+            out.push('{');
+            rest = &rest[start+1..];
+            continue;
+        }
+
+        let end = rest[start..].find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| String::from("unclosed {"))?;
+        let field = &rest[start+1..end];
+
+        let value = match field {
+            "name" => name.to_owned(),
+            "stem" => stem.to_owned(),
+            "ext" => ext.to_owned(),
+            "n" => n.to_string(),
+            "date" => today.to_owned(),
+            "mtime" => fs::symlink_metadata(dir.join(name))
+                .map(|meta| info::format_date(meta.mtime()))
+                .map_err(|e| format!("{}: {}", name, io_error_string(&e)))?,
+            _ => match field.strip_prefix("n:").and_then(|w| w.parse::<usize>().ok()) {
+                Some(width) => format!("{:01$}", n, width),
+                None => return Err(format!("unknown field {{{}}}", field)),
+            },
+        };
+
+        match regex {
+            true  => out.push_str(&value.replace('$', "$$")),
+            false => out.push_str(&value),
+        }
+        rest = &rest[end+1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+// The names of the entries named `old` in `dir` after rewriting them with
+// `pattern`, in the same order. Names the regex doesn't match are kept, so
+// are all of them by an empty pattern
+pub fn substitute(dir: &Path, old: &[String], pattern: &str) -> Result<Vec<String>, String> {
+    if pattern.is_empty() {
+        return Ok(old.to_vec());
+    }
+    let pattern = Pattern::parse(pattern)?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let today = info::format_date(now);
+
+    old.iter()
+        .enumerate()
+        .map(|(i, name)| pattern.apply(dir, name, i + 1, &today))
+        .collect()
+}
+
+// Pairs up the `old` names of entries in `dir` with their `new` ones,
+// leaving out the unchanged. Nothing outside of them may be overwritten and
+// no two can end up with the same name
+pub fn plan(dir: &Path, old: &[OsString], new: &[String]) -> Result<Renames, String> {
+    if old.len() != new.len() {
+        return Err(format!("expected {} names, got {}", old.len(), new.len()));
+    }
+
+    let old_names: HashSet<&OsStr> = old.iter().map(|n| n.as_os_str()).collect();
+    let mut taken = HashSet::new();
+    let mut renames = vec![];
+
+    for (old, new) in old.iter().zip(new.iter()) {
+        check_name(new).map_err(|e| format!("{}: {}", display_name(old), e))?;
+
+        if !taken.insert(new.as_str()) {
+            return Err(format!("{} is given twice", new));
+        }
+        if old == OsStr::new(new) {
+            continue;
+        }
+        // Names of entries being renamed are freed up along the way
+        if !old_names.contains(OsStr::new(new)) && dir.join(new).symlink_metadata().is_ok() {
+            return Err(format!("{} already exists", new));
+        }
+
+        renames.push((old.clone(), OsString::from(new)));
+    }
+
+    Ok(renames)
+}
+
+// Renames the entries in `dir`. Each goes through a temporary name first, so
+// swaps and cycles work out. What was done is undone if one fails
+pub fn apply(dir: &Path, renames: &Renames) -> Result<(), String> {
+    let mut temps = vec![];
+
+    for (i, (old, _)) in renames.iter().enumerate() {
+        let temp = (0..)
+            .map(|n| dir.join(format!(".nv-rename-{}-{}-{}", std::process::id(), i, n)))
+            .find(|p| p.symlink_metadata().is_err())
+            .unwrap();
+
+        if let Err(e) = fs::rename(dir.join(old), &temp) {
+            undo(dir, &renames[..i], &temps, 0);
+            return Err(format!("{}: {}", display_name(old), io_error_string(&e)));
+        }
+        temps.push(temp);
+    }
+
+    for (i, ((_, new), temp)) in renames.iter().zip(temps.iter()).enumerate() {
+        let new_path = dir.join(new);
+
+        // Checked before, but something else could have taken it since
+        let res = match new_path.symlink_metadata() {
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "already exists")),
+            Err(_) => fs::rename(temp, &new_path),
+        };
+
+        if let Err(e) = res {
+            undo(dir, renames, &temps, i);
+            return Err(format!("{}: {}", display_name(new), io_error_string(&e)));
+        }
+    }
+
+    Ok(())
+}
+
+// Puts back the first `temps.len()` entries of `renames`, of which the first
+// `done` had already got their new names
+fn undo(dir: &Path, renames: &[(OsString, OsString)], temps: &[PathBuf], done: usize) {
+    // All back to the temporary names first, like when applying
+    for ((_, new), temp) in renames.iter().zip(temps.iter()).take(done) {
+        let _ = fs::rename(dir.join(new), temp);
+    }
+    for ((old, _), temp) in renames.iter().zip(temps.iter()) {
+        let _ = fs::rename(temp, dir.join(old));
+    }
+}
+
+// Lets the names be edited in $VISUAL or $EDITOR, one per line, and returns
+// them as they were saved. The terminal has to be handed over beforehand
+pub fn edit_names(names: &[String]) -> io::Result<Vec<String>> {
+    let (path, mut file) = create_temp_file()?;
+
+    let res = names.iter()
+        .try_for_each(|name| writeln!(file, "{}", name))
+        .and_then(|_| run_editor(&path))
+        .and_then(|_| fs::read_to_string(&path));
+
+    let _ = fs::remove_file(&path);
+
+    Ok(res?.lines().map(String::from).collect())
+}
+
+// A new file only we can read with a random name in the temp dir. It has
+// to be new, the temp dir is shared and a file or symlink already there
+// could be anyone's
+fn create_temp_file() -> io::Result<(PathBuf, File)> {
+    for _ in 0..100 {
+        // Hashers are seeded randomly, so even hashing nothing gives a
+        // random number
+        let id = RandomState::new().build_hasher().finish();
+        let path = std::env::temp_dir().join(format!("nv-rename-{:016x}.txt", id));
+
+        match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no free temporary file name"))
+}
+
+fn run_editor(path: &Path) -> io::Result<()> {
+    let editor = ["VISUAL", "EDITOR"].iter()
+        .filter_map(std::env::var_os)
+        .find(|e| !e.is_empty())
+        .unwrap_or_else(|| OsString::from("vi"));
+
+    // Through the shell, the editor may come with arguments
+    let mut script = editor;
+    script.push(" \"$1\"");
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .arg("sh")
+        .arg(path)
+        .status()?;
+
+    match status.success() {
+        true  => Ok(()),
+        false => Err(io::Error::other("the editor failed, nothing renamed")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestDir;
+
+    // Makes a file for each name in `dir` with the name as its contents
+    fn populate(dir: &Path, names: &[&str]) {
+        for name in names {
+            fs::write(dir.join(name), name).unwrap();
+        }
+    }
+
+    fn contents(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    fn os(names: &[&str]) -> Vec<OsString> {
+        names.iter().map(OsString::from).collect()
+    }
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&n| String::from(n)).collect()
+    }
+
+    // The new name of `name` as the `n`th entry, the date is always 2024-01-02
+    fn rewrite(pattern: &str, name: &str, n: usize) -> Result<String, String> {
+        Pattern::parse(pattern)?.apply(Path::new("/nonexistent"), name, n, "2024-01-02")
+    }
+
+    #[test]
+    fn bad_patterns() {
+        assert!(Pattern::parse("s/a").is_err());
+        assert_eq!(Pattern::parse("s/a/b/x").err(), Some(String::from("unknown flag x")));
+        // Just the message of the regex error, on one line
+        let error = Pattern::parse("s/(/b/").err().unwrap();
+        assert!(!error.is_empty() && !error.contains('\n'));
+    }
+
+    #[test]
+    fn templates() {
+        assert_eq!(rewrite("{stem}_{n:3}.{ext}", "photo.jpg", 7), Ok(String::from("photo_007.jpg")));
+        assert_eq!(rewrite("{n}-{name}", "a.tar.gz", 12), Ok(String::from("12-a.tar.gz")));
+        assert_eq!(rewrite("{stem}|{ext}", ".hidden", 1), Ok(String::from(".hidden|")));
+        assert_eq!(rewrite("{date} {name}", "x", 1), Ok(String::from("2024-01-02 x")));
+        assert_eq!(rewrite("{nope}", "x", 1), Err(String::from("unknown field {nope}")));
+        assert_eq!(rewrite("{name", "x", 1), Err(String::from("unclosed {")));
+    }
+
+    #[test]
+    fn substitutions() {
+        assert_eq!(rewrite("s/a/X/", "banana", 1), Ok(String::from("bXnana")));
+        assert_eq!(rewrite("s/a/X/g", "banana", 1), Ok(String::from("bXnXnX")));
+        assert_eq!(rewrite("s/A/x/gi", "BANANA", 1), Ok(String::from("BxNxNx")));
+        assert_eq!(rewrite("s/nomatch/x/", "keep", 1), Ok(String::from("keep")));
+        assert_eq!(rewrite("s/(\\w+)\\.txt/${1}_{n}.md/", "notes.txt", 2), Ok(String::from("notes_2.md")));
+        assert_eq!(rewrite("s/(?P<base>.*)/{n}_$base/", "x", 3), Ok(String::from("3_x")));
+        // A $ in a field's value is taken literally
+        assert_eq!(rewrite("s/^/{name}/", "$1", 1), Ok(String::from("$1$1")));
+    }
+
+    #[test]
+    fn mtime_field() {
+        let dir = TestDir::new("mtime");
+        populate(&dir, &["a"]);
+        let mtime = info::format_date(fs::metadata(dir.join("a")).unwrap().mtime());
+
+        let pattern = Pattern::parse("{mtime}_{name}").unwrap();
+        assert_eq!(pattern.apply(&dir, "a", 1, "today"), Ok(format!("{}_a", mtime)));
+        assert!(pattern.apply(&dir, "missing", 1, "today").is_err());
+    }
+
+    #[test]
+    fn plan_leaves_out_unchanged() {
+        let dir = TestDir::new("plan");
+        populate(&dir, &["a", "b"]);
+
+        let renames = plan(&dir, &os(&["a", "b"]), &strings(&["a", "c"])).unwrap();
+        assert_eq!(renames, vec![(OsString::from("b"), OsString::from("c"))]);
+    }
+
+    #[test]
+    fn plan_rejects_clashes() {
+        let dir = TestDir::new("plan-clash");
+        populate(&dir, &["a", "b", "other"]);
+        let old = os(&["a", "b"]);
+
+        assert!(plan(&dir, &old, &strings(&["a"])).is_err());
+        assert!(plan(&dir, &old, &strings(&["c", "c"])).is_err());
+        assert!(plan(&dir, &old, &strings(&["b", "b"])).is_err());
+        assert!(plan(&dir, &old, &strings(&["other", "b"])).is_err());
+        assert!(plan(&dir, &old, &strings(&["x/y", "b"])).is_err());
+        assert!(plan(&dir, &old, &strings(&["", "b"])).is_err());
+        // Taken by an entry that's renamed as well
+        assert!(plan(&dir, &old, &strings(&["b", "c"])).is_ok());
+    }
+
+    #[test]
+    fn swaps_and_cycles() {
+        let dir = TestDir::new("cycle");
+        populate(&dir, &["a", "b", "c", "d", "e"]);
+
+        let old = os(&["a", "b", "c", "d", "e"]);
+        let renames = plan(&dir, &old, &strings(&["b", "a", "d", "e", "c"])).unwrap();
+        apply(&dir, &renames).unwrap();
+
+        assert_eq!(contents(&dir, "a"), "b");
+        assert_eq!(contents(&dir, "b"), "a");
+        assert_eq!(contents(&dir, "c"), "e");
+        assert_eq!(contents(&dir, "d"), "c");
+        assert_eq!(contents(&dir, "e"), "d");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 5);
+    }
+
+    #[test]
+    fn failed_apply_is_undone() {
+        let dir = TestDir::new("undo");
+        populate(&dir, &["a", "b"]);
+
+        let renames = plan(&dir, &os(&["a", "b"]), &strings(&["b", "c"])).unwrap();
+        // Taken after planning
+        populate(&dir, &["c"]);
+        assert!(apply(&dir, &renames).is_err());
+
+        assert_eq!(contents(&dir, "a"), "a");
+        assert_eq!(contents(&dir, "b"), "b");
+        assert_eq!(contents(&dir, "c"), "c");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::os::unix::fs::MetadataExt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc::Sender,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Event;
+
+// how often the running total of a dir is reported
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// Sums up the sizes of everything under each of `dirs` one after the other
+// in the background. Running totals are reported as `Event::DirSize` and
+// the final one is marked done. Setting the returned flag stops the work
+pub fn spawn(dirs: Vec<PathBuf>, events: Sender<Event>) -> Arc<AtomicBool> {
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    {
+        let cancel = cancel.clone();
+
+        thread::spawn(move || {
+            for dir in dirs {
+                let mut total = 0;
+                let mut seen = HashSet::new();
+                let mut last_send = Instant::now();
+
+                let finished = walk(&dir, &cancel, &mut seen, &mut |size| {
+                    total += size;
+
+                    if last_send.elapsed() >= PROGRESS_INTERVAL {
+                        last_send = Instant::now();
+                        return events.send(Event::DirSize(dir.clone(), total, false)).is_ok();
+                    }
+                    true
+                });
+
+                if !finished || events.send(Event::DirSize(dir, total, true)).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    cancel
+}
+
+// Sums up the sizes of the whole subtree of `root` in the background,
+// keeping the total of every dir in it. The bytes counted so far are
+// reported as `Event::UsageProgress` and the totals as `Event::UsageDone`.
+// Setting the returned flag stops the work
+pub fn spawn_tree(root: PathBuf, events: Sender<Event>) -> Arc<AtomicBool> {
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    {
+        let cancel = cancel.clone();
+
+        thread::spawn(move || {
+            let mut totals = HashMap::new();
+            let mut seen = HashSet::new();
+            let mut scanned = 0;
+            let mut last_send = Instant::now();
+
+            let finished = walk_tree(&root, &cancel, &mut seen, &mut totals, &mut |size| {
+                scanned += size;
+
+                if last_send.elapsed() >= PROGRESS_INTERVAL {
+                    last_send = Instant::now();
+                    return events.send(Event::UsageProgress(root.clone(), scanned)).is_ok();
+                }
+                true
+            });
+
+            if finished.is_some() {
+                let _ = events.send(Event::UsageDone(root, totals));
+            }
+        });
+    }
+
+    cancel
+}
+
+// Like `walk` but records the total of every dir in `totals`.
+// Returns the total of `dir`, or None if stopped early
+fn walk_tree(
+    dir: &Path,
+    cancel: &AtomicBool,
+    seen: &mut HashSet<(u64, u64)>,
+    totals: &mut HashMap<PathBuf, u64>,
+    add: &mut dyn FnMut(u64) -> bool,
+) -> Option<u64> {
+    let mut total = 0;
+
+    if let Ok(entries) = dir.read_dir() {
+        for e in entries {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let (path, meta) = match e.and_then(|e| Ok((e.path(), e.metadata()?))) {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+
+            if meta.is_dir() {
+                total += walk_tree(&path, cancel, seen, totals, add)?;
+                continue;
+            }
+
+            if meta.nlink() > 1 && !seen.insert((meta.dev(), meta.ino())) {
+                continue;
+            }
+
+            total += meta.len();
+            if !add(meta.len()) {
+                return None;
+            }
+        }
+    }
+
+    totals.insert(dir.to_owned(), total);
+    Some(total)
+}
+
+// Calls `add` with the size of every file under `dir`. Symlinks aren't
+// followed and hard linked files only count once.
+// Stops early when `add` returns false or the work is cancelled
+fn walk(
+    dir: &Path,
+    cancel: &AtomicBool,
+    seen: &mut HashSet<(u64, u64)>,
+    add: &mut dyn FnMut(u64) -> bool,
+) -> bool {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return true,
+    };
+
+    for e in entries {
+        if cancel.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let (path, meta) = match e.and_then(|e| Ok((e.path(), e.metadata()?))) {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+
+        if meta.is_dir() {
+            if !walk(&path, cancel, seen, add) {
+                return false;
+            }
+            continue;
+        }
+
+        if meta.nlink() > 1 && !seen.insert((meta.dev(), meta.ino())) {
+            continue;
+        }
+
+        if !add(meta.len()) {
+            return false;
+        }
+    }
+
+    true
+}
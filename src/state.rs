@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::ffi::{
+    OsStr,
+    OsString,
+};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+// older positions are forgotten past this many dirs
+const MAX_POSITIONS: usize = 1000;
+
+// The selected entry and scroll offset of every dir visited, kept across
+// sessions
+pub struct Positions {
+    saved: HashMap<PathBuf, (OsString, usize)>,
+    // the dirs by when they were left, most recent first
+    order: Vec<PathBuf>,
+}
+
+// $XDG_STATE_HOME/nv/positions
+fn state_file() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+
+    Some(state_home.join("nv").join("positions"))
+}
+
+impl Positions {
+    // Starts out empty if there is no state file yet or it can't be read
+    pub fn load() -> Self {
+        let data = state_file()
+            .and_then(|path| fs::read(path).ok())
+            .unwrap_or_default();
+
+        Self::parse(&data)
+    }
+
+    // NUL terminated "dir", "name" and "scroll" fields, names can be
+    // anything but NUL. The dirs are in the same order as they're kept
+    fn parse(data: &[u8]) -> Self {
+        let mut positions = Self {
+            saved: HashMap::new(),
+            order: vec![],
+        };
+
+        let fields: Vec<&[u8]> = data.split(|&b| b == 0).collect();
+        for rec in fields.chunks_exact(3) {
+            let scroll = match std::str::from_utf8(rec[2]).ok().and_then(|s| s.parse().ok()) {
+                Some(scroll) => scroll,
+                None => continue,
+            };
+
+            let dir = PathBuf::from(OsStr::from_bytes(rec[0]));
+            let name = OsStr::from_bytes(rec[1]).to_owned();
+            if positions.saved.insert(dir.clone(), (name, scroll)).is_none() {
+                positions.order.push(dir);
+            }
+        }
+
+        positions
+    }
+
+    pub fn get(&self, dir: &Path) -> Option<(&OsStr, usize)> {
+        self.saved.get(dir).map(|(name, scroll)| (name.as_os_str(), *scroll))
+    }
+
+    pub fn set(&mut self, dir: PathBuf, name: OsString, scroll: usize) {
+        self.order.retain(|d| *d != dir);
+        self.order.insert(0, dir.clone());
+        self.saved.insert(dir, (name, scroll));
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = match state_file() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Written aside first so a crash can't leave half a file
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.serialize())?;
+        fs::rename(&tmp, &path)
+    }
+
+    // The dirs left the longest ago are dropped when there are too many
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![];
+        for dir in self.order.iter().take(MAX_POSITIONS) {
+            let (ref name, scroll) = self.saved[dir];
+
+            data.extend_from_slice(dir.as_os_str().as_bytes());
+            data.push(0);
+            data.extend_from_slice(name.as_bytes());
+            data.push(0);
+            data.extend_from_slice(scroll.to_string().as_bytes());
+            data.push(0);
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let odd_name = OsStr::from_bytes(b"new\nline \xff");
+
+        let mut positions = Positions::parse(b"");
+        positions.set(PathBuf::from("/a"), OsString::from("x"), 3);
+        positions.set(PathBuf::from("/b"), odd_name.to_owned(), 0);
+
+        let loaded = Positions::parse(&positions.serialize());
+        assert_eq!(loaded.get(Path::new("/a")), Some((OsStr::new("x"), 3)));
+        assert_eq!(loaded.get(Path::new("/b")), Some((odd_name, 0)));
+        assert_eq!(loaded.get(Path::new("/c")), None);
+        assert_eq!(loaded.order, vec![PathBuf::from("/b"), PathBuf::from("/a")]);
+    }
+
+    #[test]
+    fn bad_records_are_skipped() {
+        let positions = Positions::parse(b"/a\x00x\x00nan\x00/b\x00y\x002\x00/c\x00");
+        assert_eq!(positions.get(Path::new("/a")), None);
+        assert_eq!(positions.get(Path::new("/b")), Some((OsStr::new("y"), 2)));
+        assert_eq!(positions.order, vec![PathBuf::from("/b")]);
+    }
+
+    #[test]
+    fn least_recent_are_dropped() {
+        let mut positions = Positions::parse(b"");
+        for i in 0..MAX_POSITIONS + 2 {
+            positions.set(PathBuf::from(format!("/{}", i)), OsString::from("x"), 0);
+        }
+        // Left again, so it's the most recent
+        positions.set(PathBuf::from("/0"), OsString::from("y"), 0);
+
+        let loaded = Positions::parse(&positions.serialize());
+        assert_eq!(loaded.order.len(), MAX_POSITIONS);
+        assert_eq!(loaded.get(Path::new("/0")), Some((OsStr::new("y"), 0)));
+        assert_eq!(loaded.get(Path::new("/1")), None);
+        assert_eq!(loaded.get(Path::new("/2")), None);
+        assert!(loaded.get(Path::new("/3")).is_some());
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::ffi::{
+    OsStr,
+    OsString,
+};
+use std::fs::{
+    self,
+    OpenOptions,
+};
+use std::io::{
+    self,
+    Write,
+};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use crate::{
+    Event,
+    info,
+    io_error_string,
+    ops,
+};
+
+// Where a trashed entry came from, as kept in its .trashinfo file
+pub struct TrashInfo {
+    pub path: PathBuf,
+}
+
+// $XDG_DATA_HOME/Trash, the home trash of the freedesktop.org spec
+fn trash_dir() -> Option<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+
+    Some(data_home.join("Trash"))
+}
+
+// The dir the trashed entries themselves are kept in
+pub fn files_dir() -> Option<PathBuf> {
+    trash_dir().map(|dir| dir.join("files"))
+}
+
+// The .trashinfo file of `path` if it's a trashed entry
+fn info_file(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    if path.parent()? != files_dir()? {
+        return None;
+    }
+
+    let mut info_name = name.to_owned();
+    info_name.push(".trashinfo");
+    Some(trash_dir()?.join("info").join(info_name))
+}
+
+// Moves `paths` to the trash in the background. Each path is reported as
+// `Event::Trashed` with the error if it couldn't be trashed
+pub fn trash(paths: Vec<PathBuf>, events: Sender<Event>) {
+    thread::spawn(move || {
+        for path in paths {
+            let error = trash_one(&path).err().map(|e| io_error_string(&e));
+            if events.send(Event::Trashed(path, error)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+fn trash_one(path: &Path) -> io::Result<()> {
+    let dir = trash_dir().ok_or_else(|| io::Error::other("no home dir for the trash"))?;
+    let files = dir.join("files");
+    let info = dir.join("info");
+
+    if path.starts_with(&dir) {
+        return Err(io::Error::other("already in the trash"));
+    }
+
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(&files)?;
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(&info)?;
+
+    let base = path.file_name().ok_or_else(|| io::Error::other("can't trash that"))?;
+
+    // A name is claimed by creating its info file, so two trashing at once
+    // can't pick the same one
+    let (name, info_path, mut info_file) = (0..)
+        .map(|n| {
+            let mut name = base.to_owned();
+            if n > 0 {
+                name.push(format!(".{}", n));
+            }
+            let mut info_name = name.clone();
+            info_name.push(".trashinfo");
+            (name, info.join(info_name))
+        })
+        .filter(|(name, _)| files.join(name).symlink_metadata().is_err())
+        .find_map(|(name, info_path)| {
+            match OpenOptions::new().write(true).create_new(true).open(&info_path) {
+                Ok(file) => Some(Ok((name, info_path, file))),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => None,
+                Err(e) => Some(Err(e)),
+            }
+        })
+        .unwrap()?;
+
+    let res = info_file
+        .write_all(format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(path), deletion_date()
+        ).as_bytes())
+        .and_then(|_| ops::move_tree(path, &files.join(&name)));
+
+    if res.is_err() {
+        let _ = fs::remove_file(&info_path);
+    }
+    res
+}
+
+// Forgets where the trashed entry at `path` came from once it's deleted
+// for good. Does nothing for anything else
+pub fn forget(path: &Path) {
+    if let Some(info) = info_file(path) {
+        let _ = fs::remove_file(info);
+    }
+}
+
+// Where the trashed entries came from by their names in the files dir
+pub fn list() -> HashMap<OsString, TrashInfo> {
+    let info_dir = match trash_dir() {
+        Some(dir) => dir.join("info"),
+        None => return HashMap::new(),
+    };
+    let entries = match fs::read_dir(&info_dir) {
+        Ok(entries) => entries,
+        Err(_) => return HashMap::new(),
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let file_name = e.file_name();
+            let name = file_name.as_bytes().strip_suffix(b".trashinfo")?;
+            let info = parse_info(&fs::read(e.path()).ok()?)?;
+
+            Some((OsStr::from_bytes(name).to_owned(), info))
+        })
+        .collect()
+}
+
+// Moves the trashed entries at `paths` back where they came from in the
+// background. Each one is reported as `Event::Restored` with the original
+// path, or the error if it couldn't be restored
+pub fn restore(paths: Vec<PathBuf>, events: Sender<Event>) {
+    thread::spawn(move || {
+        for path in paths {
+            let res = restore_one(&path).map_err(|e| io_error_string(&e));
+            if events.send(Event::Restored(path, res)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+fn restore_one(path: &Path) -> io::Result<PathBuf> {
+    let info_path = info_file(path).ok_or_else(|| io::Error::other("not in the trash"))?;
+    let info = parse_info(&fs::read(&info_path)?)
+        .ok_or_else(|| io::Error::other("broken .trashinfo file"))?;
+
+    // Nothing is overwritten to make room
+    if info.path.symlink_metadata().is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "original path is taken"));
+    }
+    if let Some(parent) = info.path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    ops::move_tree(path, &info.path)?;
+    fs::remove_file(&info_path)?;
+
+    Ok(info.path)
+}
+
+fn parse_info(data: &[u8]) -> Option<TrashInfo> {
+    let mut lines = data.split(|&b| b == b'\n').map(|l| l.strip_suffix(b"\r").unwrap_or(l));
+
+    if lines.next()? != b"[Trash Info]" {
+        return None;
+    }
+
+    // Relative paths are relative to the dir the trash is in
+    let path = lines
+        .filter_map(|line| line.strip_prefix(b"Path="))
+        .map(decode_path)
+        .next()?;
+    let path = match path.is_absolute() {
+        true  => path,
+        false => trash_dir()?.parent()?.join(path),
+    };
+
+    Some(TrashInfo {
+        path: path,
+    })
+}
+
+// Percent encodes all but the unreserved characters and slashes, as the
+// spec asks
+fn encode_path(path: &Path) -> String {
+    path.as_os_str().as_bytes().iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' |
+            b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn decode_path(value: &[u8]) -> PathBuf {
+    let mut bytes = vec![];
+    let mut i = 0;
+
+    while i < value.len() {
+        let hex = value.get(i+1..i+3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+
+        match (value[i], hex) {
+            (b'%', Some(b)) => {
+                bytes.push(b);
+                i += 3;
+            },
+            (b, _) => {
+                bytes.push(b);
+                i += 1;
+            },
+        }
+    }
+
+    PathBuf::from(OsStr::from_bytes(&bytes))
+}
+
+// The current local time the way .trashinfo files have it
+fn deletion_date() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let tm = match info::local_tm(now) {
+        Some(tm) => tm,
+        None => return String::from("1970-01-01T00:00:00"),
+    };
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday,
+        tm.tm_hour, tm.tm_min, tm.tm_sec
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_round_trip() {
+        let path = Path::new(OsStr::from_bytes(b"/home/x/a b%c/\xff\xc3\xa9~.-_"));
+        let encoded = encode_path(path);
+
+        assert_eq!(encoded, "/home/x/a%20b%25c/%FF%C3%A9~.-_");
+        assert_eq!(decode_path(encoded.as_bytes()), path);
+    }
+
+    #[test]
+    fn bad_escapes_are_kept() {
+        assert_eq!(decode_path(b"/a%2"), Path::new("/a%2"));
+        assert_eq!(decode_path(b"/a%zz%41"), Path::new("/a%zzA"));
+    }
+
+    #[test]
+    fn info_files() {
+        let info = |data: &[u8]| parse_info(data).map(|info| info.path);
+
+        assert_eq!(
+            info(b"[Trash Info]\r\nDeletionDate=2024-01-01T00:00:00\r\nPath=/x/a%20b\r\n"),
+            Some(PathBuf::from("/x/a b"))
+        );
+        // Only the first Path counts
+        assert_eq!(info(b"[Trash Info]\nPath=/a\nPath=/b\n"), Some(PathBuf::from("/a")));
+        assert_eq!(info(b"[Trash Info]\nDeletionDate=2024-01-01T00:00:00\n"), None);
+        assert_eq!(info(b"Path=/a\n"), None);
+
+        if let Some(base) = trash_dir().as_deref().and_then(Path::parent) {
+            assert_eq!(info(b"[Trash Info]\nPath=a/b\n"), Some(base.join("a/b")));
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::path::PathBuf;
+use std::sync::{
+    Arc,
+    Mutex,
+    mpsc::Sender,
+};
+use std::thread;
+use std::time::Duration;
+
+use crate::Event;
+
+// changes are gathered for this long before being reported, so that a burst
+// of changes (e.g. a build) only causes a single rescan
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+// Reports changes to the contents of a set of directories as
+// `Event::DirChanged`
+pub struct Watcher {
+    imp: imp::Watcher,
+    watched: HashSet<PathBuf>,
+}
+
+impl Watcher {
+    pub fn new(events: Sender<Event>) -> Option<Self> {
+        Some(Self {
+            imp: imp::Watcher::new(events)?,
+            watched: HashSet::new(),
+        })
+    }
+
+    // Watches exactly `dirs` from now on.
+    // Returns the dirs that weren't watched before
+    pub fn watch_only(&mut self, dirs: &[PathBuf]) -> Vec<PathBuf> {
+        let dirs: HashSet<PathBuf> = dirs.iter().cloned().collect();
+
+        for dir in self.watched.difference(&dirs) {
+            self.imp.unwatch(dir);
+        }
+
+        let added: Vec<PathBuf> = dirs.difference(&self.watched).cloned().collect();
+        for dir in added.iter() {
+            self.imp.watch(dir);
+        }
+
+        self.watched = dirs;
+        added
+    }
+}
+
+// Sends one event per changed dir
+fn report(events: &Sender<Event>, changed: HashSet<PathBuf>) -> bool {
+    changed.into_iter().all(|dir| events.send(Event::DirChanged(dir)).is_ok())
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::*;
+
+    use std::ffi::CString;
+    use std::mem::size_of;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use libc::c_int;
+
+    const WATCH_MASK: u32 = libc::IN_MODIFY | libc::IN_ATTRIB | libc::IN_MOVED_FROM | libc::IN_MOVED_TO
+                          | libc::IN_CREATE | libc::IN_DELETE | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF
+                          | libc::IN_ONLYDIR;
+
+    // A file descriptor closed once the last of its users is done with it,
+    // so the reader never ends up reading from a reused number
+    struct Fd(c_int);
+
+    impl Drop for Fd {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.0); }
+        }
+    }
+
+    pub struct Watcher {
+        fd: Arc<Fd>,
+        wake: Arc<Fd>, // written to stop the reader
+        wds: Arc<Mutex<HashMap<c_int, PathBuf>>>,
+    }
+
+    impl Watcher {
+        pub fn new(events: Sender<Event>) -> Option<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return None;
+            }
+            let fd = Arc::new(Fd(fd));
+
+            let wake = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+            if wake < 0 {
+                return None;
+            }
+            let wake = Arc::new(Fd(wake));
+
+            let wds = Arc::new(Mutex::new(HashMap::new()));
+
+            {
+                let (fd, wake, wds) = (fd.clone(), wake.clone(), wds.clone());
+                thread::spawn(move || read_events(&fd, &wake, &wds, &events));
+            }
+
+            Some(Self {
+                fd: fd,
+                wake: wake,
+                wds: wds,
+            })
+        }
+
+        pub fn watch(&mut self, dir: &Path) {
+            let path = match CString::new(dir.as_os_str().as_bytes()) {
+                Ok(path) => path,
+                Err(_) => return,
+            };
+
+            let wd = unsafe { libc::inotify_add_watch(self.fd.0, path.as_ptr(), WATCH_MASK) };
+            if wd >= 0 {
+                self.wds.lock().unwrap().insert(wd, dir.to_owned());
+            }
+        }
+
+        pub fn unwatch(&mut self, dir: &Path) {
+            let mut wds = self.wds.lock().unwrap();
+
+            let wd = wds.iter().find(|(_, d)| d.as_path() == dir).map(|(wd, _)| *wd);
+            if let Some(wd) = wd {
+                unsafe { libc::inotify_rm_watch(self.fd.0, wd); }
+                wds.remove(&wd);
+            }
+        }
+    }
+
+    impl Drop for Watcher {
+        fn drop(&mut self) {
+            unsafe { libc::eventfd_write(self.wake.0, 1); }
+        }
+    }
+
+    fn read_events(fd: &Fd, wake: &Fd, wds: &Mutex<HashMap<c_int, PathBuf>>, events: &Sender<Event>) {
+        let mut buf = [0u8; 4096];
+
+        loop {
+            let mut changed = HashSet::new();
+
+            // Block for the first change, then gather for a while
+            let mut timeout = -1;
+            loop {
+                let mut pfds = [
+                    libc::pollfd { fd: fd.0, events: libc::POLLIN, revents: 0 },
+                    libc::pollfd { fd: wake.0, events: libc::POLLIN, revents: 0 },
+                ];
+
+                match unsafe { libc::poll(pfds.as_mut_ptr(), 2, timeout) } {
+                    n if n < 0 => return,
+                    0 => break,
+                    _ => (),
+                }
+                // The watcher is gone
+                if pfds[1].revents != 0 {
+                    return;
+                }
+
+                let n = unsafe {
+                    libc::read(fd.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+                };
+                if n <= 0 {
+                    return;
+                }
+
+                let wds = wds.lock().unwrap();
+                let mut ofs = 0;
+
+                while ofs + size_of::<libc::inotify_event>() <= n as usize {
+                    let event = unsafe {
+                        std::ptr::read_unaligned(buf[ofs..].as_ptr() as *const libc::inotify_event)
+                    };
+
+                    if let Some(dir) = wds.get(&event.wd) {
+                        changed.insert(dir.clone());
+                    }
+
+                    ofs += size_of::<libc::inotify_event>() + event.len as usize;
+                }
+
+                timeout = DEBOUNCE.as_millis() as c_int;
+            }
+
+            if !report(events, changed) {
+                return;
+            }
+        }
+    }
+}
+
+// Without inotify, the modification times of the watched dirs are polled
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::*;
+
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    // how often the watched dirs are checked
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub struct Watcher {
+        mtimes: Arc<Mutex<HashMap<PathBuf, Option<SystemTime>>>>,
+    }
+
+    fn mtime(dir: &Path) -> Option<SystemTime> {
+        dir.metadata().and_then(|m| m.modified()).ok()
+    }
+
+    impl Watcher {
+        pub fn new(events: Sender<Event>) -> Option<Self> {
+            let mtimes = Arc::new(Mutex::new(HashMap::new()));
+
+            {
+                let mtimes = mtimes.clone();
+                thread::spawn(move || loop {
+                    thread::sleep(POLL_INTERVAL);
+
+                    let mut changed = HashSet::new();
+                    for (dir, old) in mtimes.lock().unwrap().iter_mut() {
+                        let new = mtime(dir);
+                        if new != *old {
+                            *old = new;
+                            changed.insert(dir.clone());
+                        }
+                    }
+
+                    thread::sleep(DEBOUNCE);
+                    if !report(&events, changed) {
+                        return;
+                    }
+                });
+            }
+
+            Some(Self {
+                mtimes: mtimes,
+            })
+        }
+
+        pub fn watch(&mut self, dir: &Path) {
+            self.mtimes.lock().unwrap().insert(dir.to_owned(), mtime(dir));
+        }
+
+        pub fn unwatch(&mut self, dir: &Path) {
+            self.mtimes.lock().unwrap().remove(dir);
+        }
+    }
+}